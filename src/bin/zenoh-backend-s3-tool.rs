@@ -0,0 +1,220 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Command line tool operating on the bucket of an S3 storage without running a zenoh router.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uhlc::ID;
+use zenoh::Result as ZResult;
+use zenoh_backend_s3::client::BucketLocation;
+use zenoh_backend_s3::fsck::check_bucket;
+use zenoh_backend_s3::properties::json_schema;
+use zenoh_backend_s3::snapshot::{export_snapshot, import_snapshot};
+use zenoh_core::zerror;
+
+const USAGE: &str = r#"Usage: zenoh-backend-s3-tool <COMMAND> --bucket <BUCKET> [OPTIONS]
+
+Commands:
+  export    Export the samples of the storage to a newline-delimited JSON archive
+  import    Import the samples of an archive into the storage, keeping the most recent ones
+  fsck      Check that all the objects of the bucket can be served through zenoh
+  schema    Print the JSON schema of the `volume` field of the S3 storage configurations
+
+Bucket options:
+  --bucket <BUCKET>    Name of the bucket of the storage
+  --region <REGION>    Region of the bucket
+  --url <URL>          Endpoint of the S3 server
+  --root-ca-certificate <FILE>
+                       Certificate authority to authenticate the S3 server with TLS
+
+Export options:
+  --output <FILE>      Archive file to write
+  --at <TIME>          Export the samples as they were at this time, either RFC 3339 or seconds
+                       since the UNIX epoch (now by default), requires `--versioning`
+  --versioning         Read the object versions of the bucket (storages with `versioning`)
+
+Import options:
+  --input <FILE>       Archive file to read
+  --prefix <PREFIX>    The `strip_prefix` of the storage (required)
+  --no-prefix          The storage has no `strip_prefix`, the keys being imported as is
+
+Fsck options:
+  --repair-zid <ZID>   Stamp the objects lacking a valid timestamp with their LastModified date
+                       and this zenoh ID (hexadecimal)
+  --adopt-foreign-objects
+                       Don't report the objects lacking a content encoding (storages with
+                       `adopt_foreign_objects`)
+
+The credentials are read from the AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY environment
+variables."#;
+
+// Options which don't expect a value.
+const FLAGS: [&str; 3] = ["versioning", "adopt-foreign-objects", "no-prefix"];
+
+struct Args {
+    command: String,
+    options: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> ZResult<Self> {
+        let command = args.next().ok_or_else(|| zerror!("Missing command"))?;
+        let mut options = HashMap::new();
+        let mut flags = HashSet::new();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| zerror!("Unexpected argument '{arg}'"))?
+                .to_owned();
+            if FLAGS.contains(&name.as_str()) {
+                flags.insert(name);
+            } else {
+                let value = args
+                    .next()
+                    .ok_or_else(|| zerror!("Missing value for option '--{name}'"))?;
+                options.insert(name, value);
+            }
+        }
+        Ok(Args {
+            command,
+            options,
+            flags,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.options.get(name).cloned()
+    }
+
+    fn require(&self, name: &str) -> ZResult<String> {
+        self.get(name)
+            .ok_or_else(|| zerror!("Missing option '--{name}'").into())
+    }
+
+    fn bucket_location(&self) -> ZResult<BucketLocation> {
+        Ok(BucketLocation {
+            bucket: self.require("bucket")?,
+            region: self.get("region"),
+            endpoint: self.get("url"),
+            access_key: std::env::var("AWS_ACCESS_KEY_ID")
+                .map_err(|e| zerror!("Unable to read AWS_ACCESS_KEY_ID: {e}"))?,
+            secret_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                .map_err(|e| zerror!("Unable to read AWS_SECRET_ACCESS_KEY: {e}"))?,
+            root_ca_certificate_file: self.get("root-ca-certificate"),
+        })
+    }
+}
+
+fn parse_time(time: &str) -> ZResult<SystemTime> {
+    match time.parse::<u64>() {
+        Ok(secs) => Ok(UNIX_EPOCH + Duration::from_secs(secs)),
+        Err(_) => humantime::parse_rfc3339_weak(time)
+            .map_err(|e| zerror!("Invalid time '{time}': {e}").into()),
+    }
+}
+
+async fn export(args: &Args) -> ZResult<()> {
+    let location = args.bucket_location()?;
+    let output = args.require("output")?;
+    let at = args.get("at").map(|at| parse_time(&at)).transpose()?;
+    let versioning = args.flags.contains("versioning");
+    if at.is_some() && !versioning {
+        return Err(zerror!(
+            "`--at` requires `--versioning`: the current objects alone can't tell the samples \
+                of a past time"
+        )
+        .into());
+    }
+    let writer = BufWriter::new(File::create(&output)?);
+    let count = export_snapshot(&location, at, versioning, writer).await?;
+    println!(
+        "Exported {count} samples from '{}' to '{output}'.",
+        location.bucket
+    );
+    Ok(())
+}
+
+async fn import(args: &Args) -> ZResult<()> {
+    let location = args.bucket_location()?;
+    let input = args.require("input")?;
+    // The keys of the archive are relative to the `strip_prefix` of the storage: imported with
+    // the wrong one, the samples would silently not be served.
+    let prefix = match (args.get("prefix"), args.flags.contains("no-prefix")) {
+        (Some(_), true) => {
+            return Err(zerror!("Options '--prefix' and '--no-prefix' are exclusive").into())
+        }
+        (None, false) => {
+            return Err(zerror!(
+                "Missing option '--prefix', the `strip_prefix` of the storage (or '--no-prefix' \
+                    if it has none)"
+            )
+            .into())
+        }
+        (prefix, _) => prefix,
+    };
+    let reader = BufReader::new(File::open(&input)?);
+    let report = import_snapshot(&location, prefix, reader).await?;
+    println!(
+        "Imported {} samples from '{input}' to '{}', skipped {} older than the stored ones.",
+        report.imported, location.bucket, report.skipped
+    );
+    Ok(())
+}
+
+async fn fsck(args: &Args) -> ZResult<()> {
+    let location = args.bucket_location()?;
+    let repair_id = args
+        .get("repair-zid")
+        .map(|zid| ID::from_str(&zid).map_err(|e| zerror!("Invalid zenoh ID '{zid}': {e:?}")))
+        .transpose()?;
+    let report = check_bucket(
+        &location,
+        repair_id,
+        args.flags.contains("adopt-foreign-objects"),
+    )
+    .await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn schema() -> ZResult<()> {
+    println!("{}", serde_json::to_string_pretty(&json_schema())?);
+    Ok(())
+}
+
+async fn run() -> ZResult<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
+    match args.command.as_str() {
+        "export" => export(&args).await,
+        "import" => import(&args).await,
+        "fsck" => fsck(&args).await,
+        "schema" => schema(),
+        command => Err(zerror!("Unknown command '{command}'").into()),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let _ = env_logger::try_init();
+    if let Err(e) = run().await {
+        eprintln!("Error: {e}\n\n{USAGE}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,232 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use aws_sdk_s3::model::{
+    BucketCannedAcl, ObjectOwnership, PublicAccessBlockConfiguration, ServerSideEncryption, Tag,
+};
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+use crate::properties::{
+    BucketCreationProperties, EncryptionProperties, PublicAccessBlockProperties,
+};
+
+// Bucket creation properties
+pub const BUCKET_CREATION_PROP: &str = "bucket_creation";
+const BUCKET_ACL: &str = "acl";
+const BUCKET_OBJECT_OWNERSHIP: &str = "object_ownership";
+const BUCKET_ENCRYPTION: &str = "encryption";
+const ENCRYPTION_ALGORITHM: &str = "algorithm";
+
+/// Settings applied to the bucket of a storage when the storage creates it, declared under the
+/// `bucket_creation` field of the storage configuration:
+///
+/// ```
+/// bucket_creation: {
+///   // Enables S3 Object Lock on the bucket (which also enables its versioning).
+///   object_lock: true,
+///   // Canned ACL of the bucket.
+///   acl: "private",
+///   // Object ownership controls of the bucket.
+///   object_ownership: "BucketOwnerEnforced",
+///   // Either true to block all public access, or the individual settings (false by default).
+///   public_access_block: {
+///     block_public_acls: true,
+///     ignore_public_acls: true,
+///     block_public_policy: true,
+///     restrict_public_buckets: true,
+///   },
+///   // Default encryption of the objects, either "AES256" or "aws:kms".
+///   encryption: { algorithm: "aws:kms", kms_key_id: "arn:aws:kms:...", bucket_key: true },
+///   // Tags of the bucket.
+///   tags: { "cost-center": "1234" },
+/// }
+/// ```
+///
+/// These settings are not applied on reused buckets.
+#[derive(Clone, Default)]
+pub(crate) struct BucketCreationConfig {
+    pub object_lock: bool,
+    pub acl: Option<BucketCannedAcl>,
+    pub object_ownership: Option<ObjectOwnership>,
+    pub public_access_block: Option<PublicAccessBlockConfiguration>,
+    pub encryption: Option<EncryptionConfig>,
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Clone)]
+pub(crate) struct EncryptionConfig {
+    pub algorithm: ServerSideEncryption,
+    pub kms_key_id: Option<String>,
+    pub bucket_key: bool,
+}
+
+impl BucketCreationConfig {
+    /// Creates a new instance of [BucketCreationConfig] from the `bucket_creation` field of the
+    /// storage configuration.
+    pub fn new(properties: BucketCreationProperties) -> ZResult<Self> {
+        let acl = properties
+            .acl
+            .map(|acl| {
+                if BucketCannedAcl::values().contains(&acl.as_str()) {
+                    Ok(BucketCannedAcl::from(acl.as_str()))
+                } else {
+                    Err(zerror!(
+                        "Property `{BUCKET_CREATION_PROP}.{BUCKET_ACL}` must be one of {:?}",
+                        BucketCannedAcl::values()
+                    ))
+                }
+            })
+            .transpose()?;
+        let object_ownership = properties
+            .object_ownership
+            .map(|ownership| {
+                if ObjectOwnership::values().contains(&ownership.as_str()) {
+                    Ok(ObjectOwnership::from(ownership.as_str()))
+                } else {
+                    Err(zerror!(
+                        "Property `{BUCKET_CREATION_PROP}.{BUCKET_OBJECT_OWNERSHIP}` must be one \
+                            of {:?}",
+                        ObjectOwnership::values()
+                    ))
+                }
+            })
+            .transpose()?;
+        // ACLs are disabled on the buckets whose owner is enforced, S3 would reject the creation.
+        if object_ownership == Some(ObjectOwnership::BucketOwnerEnforced)
+            && acl
+                .as_ref()
+                .map_or(false, |acl| *acl != BucketCannedAcl::Private)
+        {
+            return Err(zerror!(
+                "Property `{BUCKET_CREATION_PROP}.{BUCKET_ACL}` must be \"private\" with \
+                    `{BUCKET_OBJECT_OWNERSHIP}: \"BucketOwnerEnforced\"`"
+            )
+            .into());
+        }
+        let public_access_block = match properties.public_access_block {
+            None | Some(PublicAccessBlockProperties::All(false)) => None,
+            Some(PublicAccessBlockProperties::All(true)) => Some(
+                PublicAccessBlockConfiguration::builder()
+                    .block_public_acls(true)
+                    .ignore_public_acls(true)
+                    .block_public_policy(true)
+                    .restrict_public_buckets(true)
+                    .build(),
+            ),
+            Some(PublicAccessBlockProperties::Settings(settings)) => Some(
+                PublicAccessBlockConfiguration::builder()
+                    .block_public_acls(settings.block_public_acls)
+                    .ignore_public_acls(settings.ignore_public_acls)
+                    .block_public_policy(settings.block_public_policy)
+                    .restrict_public_buckets(settings.restrict_public_buckets)
+                    .build(),
+            ),
+        };
+        let encryption = properties
+            .encryption
+            .map(EncryptionConfig::new)
+            .transpose()?;
+        let tags = properties
+            .tags
+            .into_iter()
+            .map(|(key, value)| Tag::builder().key(key).value(value).build())
+            .collect();
+        Ok(BucketCreationConfig {
+            object_lock: properties.object_lock,
+            acl,
+            object_ownership,
+            public_access_block,
+            encryption,
+            tags,
+        })
+    }
+}
+
+impl EncryptionConfig {
+    fn new(properties: EncryptionProperties) -> ZResult<Self> {
+        if !ServerSideEncryption::values().contains(&properties.algorithm.as_str()) {
+            return Err(zerror!(
+                "Property `{BUCKET_CREATION_PROP}.{BUCKET_ENCRYPTION}.{ENCRYPTION_ALGORITHM}` must \
+                    be one of {:?}",
+                ServerSideEncryption::values()
+            )
+            .into());
+        }
+        Ok(EncryptionConfig {
+            algorithm: ServerSideEncryption::from(properties.algorithm.as_str()),
+            kms_key_id: properties.kms_key_id,
+            bucket_key: properties.bucket_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(properties: serde_json::Value) -> ZResult<BucketCreationConfig> {
+        BucketCreationConfig::new(serde_json::from_value(properties).unwrap())
+    }
+
+    fn error(properties: serde_json::Value) -> String {
+        config(properties).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn settings_are_parsed() {
+        let config = config(json!({
+            "object_lock": true,
+            "acl": "private",
+            "object_ownership": "BucketOwnerEnforced",
+            "public_access_block": true,
+            "encryption": { "algorithm": "aws:kms", "kms_key_id": "key", "bucket_key": true },
+            "tags": { "cost-center": "1234" },
+        }))
+        .unwrap();
+        assert!(config.object_lock);
+        assert_eq!(config.acl, Some(BucketCannedAcl::Private));
+        assert_eq!(
+            config.object_ownership,
+            Some(ObjectOwnership::BucketOwnerEnforced)
+        );
+        let public_access_block = config.public_access_block.unwrap();
+        assert!(public_access_block.block_public_acls());
+        assert!(public_access_block.restrict_public_buckets());
+        let encryption = config.encryption.unwrap();
+        assert_eq!(encryption.algorithm, ServerSideEncryption::AwsKms);
+        assert_eq!(encryption.kms_key_id.as_deref(), Some("key"));
+        assert_eq!(config.tags.len(), 1);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(error(json!({ "acl": "secret" })).contains("`bucket_creation.acl`"));
+        assert!(error(json!({ "object_ownership": "Nobody" }))
+            .contains("`bucket_creation.object_ownership`"));
+        assert!(error(json!({ "encryption": { "algorithm": "rot13" } }))
+            .contains("`bucket_creation.encryption.algorithm`"));
+    }
+
+    #[test]
+    fn acls_must_be_private_when_the_bucket_owner_is_enforced() {
+        let e = error(json!({ "acl": "public-read", "object_ownership": "BucketOwnerEnforced" }));
+        assert!(e.contains("must be \"private\""), "{e}");
+        assert!(
+            config(json!({ "acl": "public-read", "object_ownership": "ObjectWriter" })).is_ok()
+        );
+        assert!(config(json!({ "object_ownership": "BucketOwnerEnforced" })).is_ok());
+    }
+}
@@ -0,0 +1,350 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+use zenoh::time::Timestamp;
+use zenoh::Result as ZResult;
+use zenoh_buffers::buffer::SplitBuffer;
+use zenoh_core::zerror;
+use zenoh_keyexpr::{keyexpr, OwnedKeyExpr};
+
+use crate::properties::CacheProperties;
+use crate::utils::KeyExprRules;
+
+// Cache properties
+pub const CACHE_PROP: &str = "cache";
+const CACHE_INCLUDE: &str = "include";
+
+// Size accounted for each cache entry besides its key and its payload.
+const CACHE_ENTRY_OVERHEAD: usize = 64;
+
+/// Configuration of the in-memory read-through cache of a storage, declared under the `cache`
+/// field of the storage configuration:
+///
+/// ```
+/// cache: {
+///   // Maximum size of the cached keys and payloads, in bytes (64 MiB by default).
+///   max_size: 16777216,
+///   // Time, in seconds, after which a cached sample is revalidated against the ETag of its
+///   // object (60 by default).
+///   ttl: 5,
+///   // Key expressions (relative to `strip_prefix`) of the cached keys, all by default.
+///   include: ["dashboards/**"],
+/// }
+/// ```
+#[derive(Clone)]
+pub(crate) struct CacheConfig {
+    pub max_size: usize,
+    pub ttl: Duration,
+    pub include: KeyExprRules<()>,
+}
+
+impl CacheConfig {
+    /// Creates a new instance of [CacheConfig] from the `cache` field of the storage
+    /// configuration.
+    pub fn new(properties: CacheProperties) -> ZResult<Self> {
+        let include = properties
+            .include
+            .into_iter()
+            .map(|key_expr| {
+                let key_expr = OwnedKeyExpr::try_from(key_expr.as_str()).map_err(|e| {
+                    zerror!(
+                        "Invalid key expression '{key_expr}' in `{CACHE_PROP}.{CACHE_INCLUDE}`: \
+                            {e}"
+                    )
+                })?;
+                Ok((key_expr, ()))
+            })
+            .collect::<ZResult<Vec<_>>>()?;
+        Ok(CacheConfig {
+            max_size: properties.max_size,
+            ttl: Duration::from_secs(properties.ttl),
+            include: KeyExprRules::new(include),
+        })
+    }
+}
+
+struct CacheEntry {
+    /// The cached sample, None if the key is known to have no sample.
+    stored: Option<(Timestamp, zenoh::value::Value)>,
+    /// ETag of the object the sample was read from or written to, if known.
+    etag: Option<String>,
+    size: usize,
+    deadline: SystemTime,
+    /// Time at which the sample expires according to the `ttl` of the storage, if any.
+    expiry: Option<SystemTime>,
+    /// Position of the entry in the LRU order.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<OwnedKeyExpr, CacheEntry>,
+    /// Keys of the entries, from the least recently used to the most recently used.
+    lru: BTreeMap<u64, OwnedKeyExpr>,
+    tick: u64,
+    size: usize,
+    hits: u64,
+    misses: u64,
+    revalidations: u64,
+    evictions: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &keyexpr) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.tick);
+        self.size -= entry.size;
+        Some(entry)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// Outcome of a cache lookup.
+pub(crate) enum CacheLookup {
+    /// The cached sample, None if the key is known to have no sample.
+    Hit(Option<(Timestamp, zenoh::value::Value)>),
+    /// The cached sample is outdated, but it may be revalidated against the ETag of its object.
+    Stale((Timestamp, zenoh::value::Value), String),
+    Miss,
+}
+
+/// Size-bounded LRU cache of the samples of a storage, keyed by the stripped keys. It's populated
+/// by the GET operations and updated by the PUT and DELETE operations of the storage.
+pub(crate) struct Cache {
+    config: CacheConfig,
+    state: Mutex<CacheState>,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Cache {
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    fn includes(&self, key: &keyexpr) -> bool {
+        self.config.include.is_empty() || self.config.include.lookup(key).is_some()
+    }
+
+    /// Looks the key up. Outdated samples whose ETag is known are reported as stale, so that they
+    /// can be revalidated with a conditional GET rather than downloaded again.
+    pub fn lookup(&self, key: &keyexpr) -> CacheLookup {
+        if !self.includes(key) {
+            return CacheLookup::Miss;
+        }
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let now = SystemTime::now();
+        let entry = match state.entries.get(key) {
+            Some(entry) => entry,
+            None => {
+                state.misses += 1;
+                return CacheLookup::Miss;
+            }
+        };
+        if entry.deadline > now {
+            state.hits += 1;
+            let stored = entry.stored.clone();
+            let previous_tick = entry.tick;
+            let tick = state.next_tick();
+            state.entries.get_mut(key).unwrap().tick = tick;
+            let key = state.lru.remove(&previous_tick).unwrap();
+            state.lru.insert(tick, key);
+            return CacheLookup::Hit(stored);
+        }
+        let alive = entry.expiry.map_or(true, |expiry| expiry > now);
+        let stale = match (&entry.stored, &entry.etag) {
+            (Some(stored), Some(etag)) if alive => Some((stored.clone(), etag.to_owned())),
+            _ => None,
+        };
+        match stale {
+            Some((stored, etag)) => {
+                state.revalidations += 1;
+                CacheLookup::Stale(stored, etag)
+            }
+            None => {
+                state.misses += 1;
+                state.remove(key);
+                CacheLookup::Miss
+            }
+        }
+    }
+
+    /// Caches the sample of the key (None if the key has no sample) along with the ETag of its
+    /// object. The entry is fresh until the cache TTL elapses, or until `expiry` if prior.
+    pub fn insert(
+        &self,
+        key: OwnedKeyExpr,
+        stored: Option<(Timestamp, zenoh::value::Value)>,
+        etag: Option<String>,
+        expiry: Option<SystemTime>,
+    ) {
+        if !self.includes(&key) {
+            return;
+        }
+        let size = CACHE_ENTRY_OVERHEAD
+            + key.len()
+            + stored
+                .as_ref()
+                .map_or(0, |(_, value)| value.payload.contiguous().len());
+        let mut deadline = SystemTime::now() + self.config.ttl;
+        if let Some(expiry) = expiry {
+            deadline = deadline.min(expiry);
+        }
+
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.remove(&key);
+        if size > self.config.max_size {
+            return;
+        }
+        while state.size + size > self.config.max_size {
+            let lru_key = match state.lru.values().next() {
+                Some(key) => key.to_owned(),
+                None => break,
+            };
+            state.remove(&lru_key);
+            state.evictions += 1;
+        }
+        let tick = state.next_tick();
+        state.lru.insert(tick, key.clone());
+        state.size += size;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                stored,
+                etag,
+                size,
+                deadline,
+                expiry,
+                tick,
+            },
+        );
+    }
+
+    /// Statistics of the cache, reported in the admin status of the storage.
+    pub fn status(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "entries": state.entries.len(),
+            "size": state.size,
+            "max_size": self.config.max_size,
+            "hits": state.hits,
+            "misses": state.misses,
+            "revalidations": state.revalidations,
+            "evictions": state.evictions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD_SIZE: usize = 10;
+    // Size accounted for an entry of a single character key.
+    const ENTRY_SIZE: usize = CACHE_ENTRY_OVERHEAD + 1 + PAYLOAD_SIZE;
+
+    fn cache(max_size: usize, ttl: Duration) -> Cache {
+        Cache::new(CacheConfig {
+            max_size,
+            ttl,
+            include: KeyExprRules::default(),
+        })
+    }
+
+    fn key(key: &str) -> OwnedKeyExpr {
+        OwnedKeyExpr::try_from(key).unwrap()
+    }
+
+    fn sample() -> Option<(Timestamp, zenoh::value::Value)> {
+        Some((
+            uhlc::HLC::default().new_timestamp(),
+            zenoh::value::Value::from(vec![0u8; PAYLOAD_SIZE]),
+        ))
+    }
+
+    fn is_hit(cache: &Cache, key: &str) -> bool {
+        matches!(
+            cache.lookup(keyexpr::new(key).unwrap()),
+            CacheLookup::Hit(_)
+        )
+    }
+
+    #[test]
+    fn the_least_recently_used_entries_are_evicted() {
+        let cache = cache(2 * ENTRY_SIZE, Duration::from_secs(60));
+        cache.insert(key("a"), sample(), None, None);
+        cache.insert(key("b"), sample(), None, None);
+        // "a" becomes the most recently used entry, hence "b" is evicted to make room for "c".
+        assert!(is_hit(&cache, "a"));
+        cache.insert(key("c"), sample(), None, None);
+        assert!(!is_hit(&cache, "b"));
+        assert!(is_hit(&cache, "a"));
+        assert!(is_hit(&cache, "c"));
+        let status = cache.status();
+        assert_eq!(status["entries"], 2);
+        assert_eq!(status["size"], 2 * ENTRY_SIZE);
+        assert_eq!(status["evictions"], 1);
+    }
+
+    #[test]
+    fn entries_larger_than_the_cache_are_not_cached() {
+        let cache = cache(ENTRY_SIZE - 1, Duration::from_secs(60));
+        cache.insert(key("a"), sample(), None, None);
+        assert!(!is_hit(&cache, "a"));
+        assert_eq!(cache.status()["size"], 0);
+    }
+
+    #[test]
+    fn outdated_entries_are_revalidated_against_their_etag() {
+        let cache = cache(16 * ENTRY_SIZE, Duration::ZERO);
+        cache.insert(key("a"), sample(), Some("etag".to_string()), None);
+        cache.insert(key("b"), sample(), None, None);
+        match cache.lookup(keyexpr::new("a").unwrap()) {
+            CacheLookup::Stale(_, etag) => assert_eq!(etag, "etag"),
+            _ => panic!("outdated entry with an ETag should be stale"),
+        }
+        // Without an ETag the outdated entry is dropped.
+        assert!(matches!(
+            cache.lookup(keyexpr::new("b").unwrap()),
+            CacheLookup::Miss
+        ));
+        assert_eq!(cache.status()["entries"], 1);
+        assert_eq!(cache.status()["revalidations"], 1);
+    }
+
+    #[test]
+    fn expired_samples_are_not_revalidated() {
+        let cache = cache(16 * ENTRY_SIZE, Duration::from_secs(60));
+        let expiry = SystemTime::now() - Duration::from_secs(1);
+        cache.insert(key("a"), sample(), Some("etag".to_string()), Some(expiry));
+        assert!(matches!(
+            cache.lookup(keyexpr::new("a").unwrap()),
+            CacheLookup::Miss
+        ));
+        assert_eq!(cache.status()["entries"], 0);
+    }
+}
@@ -0,0 +1,564 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::{Infallible, TryFrom};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::Value;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use zenoh::prelude::r#async::*;
+use zenoh::Result as ZResult;
+use zenoh::Session;
+use zenoh_core::zerror;
+
+use crate::client::S3Client;
+use crate::properties::{ChangeDetectionMode, ChangeDetectionProperties};
+use crate::session::StorageSession;
+use crate::utils::percent_decode;
+use crate::{NONE_KEY, TIMESTAMP_METADATA_KEY};
+
+// Change detection properties
+pub const CHANGE_DETECTION_PROP: &str = "change_detection";
+const CHANGE_DETECTION_ENDPOINT: &str = "endpoint";
+const CHANGE_DETECTION_TOKEN: &str = "token";
+
+/// Configuration of the detection of the objects written into the bucket by other producers than
+/// zenoh (batch jobs, the AWS console...), declared under the `change_detection` field of the
+/// storage configuration:
+///
+/// ```
+/// change_detection: {
+///   // Either "poll" (default), listing the bucket every `interval` seconds, or "events",
+///   // receiving the S3 event notifications of the bucket posted to `endpoint`.
+///   mode: "poll",
+///   interval: 10,
+///   // Address the webhook receiving the event notifications binds to.
+///   endpoint: "127.0.0.1:9871",
+///   // Token the notifications posted to the webhook must hold in their `Authorization` header,
+///   // either as is or as a bearer token. Notifications are not authenticated if not specified.
+///   token: "${file:/var/run/secrets/s3/webhook_token}",
+/// }
+/// ```
+///
+/// The detected creations and deletions are published as zenoh samples on the key expressions
+/// of the objects, by the session of the storage (see the `connect` property). Objects holding a
+/// zenoh timestamp were written through zenoh, hence already published, and are ignored. The
+/// published objects are left as is: the storage doesn't write back the samples it receives from
+/// the change detection, and only serves them with `adopt_foreign_objects`.
+#[derive(Clone)]
+pub(crate) struct ChangeDetectionConfig {
+    pub source: ChangeSource,
+}
+
+#[derive(Clone)]
+pub(crate) enum ChangeSource {
+    /// The bucket is listed with the given period, detecting changes through the ETag and
+    /// LastModified date of the objects.
+    Poll(Duration),
+    /// S3 event notifications are posted to a webhook listening on the given address, holding
+    /// the given token if any.
+    Events(SocketAddr, Option<String>),
+}
+
+impl ChangeDetectionConfig {
+    /// Creates a new instance of [ChangeDetectionConfig] from the `change_detection` field of the
+    /// storage configuration.
+    pub fn new(properties: ChangeDetectionProperties) -> ZResult<Self> {
+        let source = match properties.mode {
+            ChangeDetectionMode::Poll => {
+                ChangeSource::Poll(Duration::from_secs(properties.interval.get()))
+            }
+            ChangeDetectionMode::Events => {
+                let token = match properties.token {
+                    Some(token) if token.is_empty() => {
+                        return Err(zerror!(
+                            "Property `{CHANGE_DETECTION_PROP}.{CHANGE_DETECTION_TOKEN}` must be \
+                                a non-empty string"
+                        )
+                        .into())
+                    }
+                    token => token,
+                };
+                let endpoint = properties.endpoint.ok_or_else(|| {
+                    zerror!(
+                        "Property `{CHANGE_DETECTION_PROP}.{CHANGE_DETECTION_ENDPOINT}` must be \
+                            specified in \"events\" mode"
+                    )
+                })?;
+                let addr = SocketAddr::from_str(&endpoint).map_err(|e| {
+                    zerror!(
+                        "Invalid `{CHANGE_DETECTION_PROP}.{CHANGE_DETECTION_ENDPOINT}` \
+                            '{endpoint}': {e}"
+                    )
+                })?;
+                if token.is_none() && !addr.ip().is_loopback() {
+                    log::warn!(
+                        "The event notifications webhook listening on {} isn't authenticated, \
+                            consider setting `{CHANGE_DETECTION_PROP}.{CHANGE_DETECTION_TOKEN}`.",
+                        addr
+                    );
+                }
+                ChangeSource::Events(addr, token)
+            }
+        };
+        Ok(ChangeDetectionConfig { source })
+    }
+}
+
+/// Change of an object of the bucket.
+enum ObjectEvent {
+    Created { key: String, etag: Option<String> },
+    Removed { key: String },
+}
+
+/// State of an object of the bucket as last seen by the [ChangeDetector].
+struct ObjectState {
+    etag: Option<String>,
+    last_modified: Option<(i64, u32)>,
+    /// Whether the object was written by another producer than zenoh, its deletion being then
+    /// published.
+    foreign: bool,
+}
+
+/// Foreign objects published by the change detection, by object name (without leading '/'),
+/// along with a digest of their content. The storage receives the published samples back, which
+/// must not be written into the bucket: this would replace the objects, their content type and
+/// user metadata with the zenoh ones.
+#[derive(Default)]
+pub(crate) struct PublishedObjects(Mutex<HashMap<String, u64>>);
+
+impl PublishedObjects {
+    fn insert(&self, key: &str, payload: &[u8]) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), digest(payload));
+    }
+
+    /// Returns true if the sample received by the storage on the key is the publication of a
+    /// foreign object, which is then forgotten.
+    pub fn take(&self, key: &str, payload: &[u8]) -> bool {
+        let mut published = self.0.lock().unwrap();
+        if published.get(key) == Some(&digest(payload)) {
+            published.remove(key);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn digest(payload: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns true if the metadata of an object holds a zenoh timestamp, that is if the object was
+/// written through zenoh.
+fn has_timestamp(metadata: Option<&HashMap<String, String>>) -> bool {
+    metadata.map_or(false, |metadata| {
+        metadata.contains_key(TIMESTAMP_METADATA_KEY)
+    })
+}
+
+struct ChangeDetector {
+    client: Arc<S3Client>,
+    session: Arc<Session>,
+    path_prefix: Option<String>,
+    objects: HashMap<String, ObjectState>,
+    published: Arc<PublishedObjects>,
+}
+
+/// Detects the objects written into the bucket by other producers than zenoh and publishes them
+/// through the session of the storage, until the task is aborted.
+pub(crate) async fn run_change_detection(
+    client: Arc<S3Client>,
+    path_prefix: Option<String>,
+    config: ChangeDetectionConfig,
+    session: Arc<StorageSession>,
+    published: Arc<PublishedObjects>,
+) {
+    let session = match session.get().await {
+        Ok(session) => session,
+        Err(e) => {
+            log::warn!("Unable to open session of '{}': {}", client, e);
+            return;
+        }
+    };
+    let mut detector = ChangeDetector {
+        client,
+        session,
+        path_prefix,
+        objects: HashMap::new(),
+        published,
+    };
+
+    // The objects already in the bucket are not published.
+    if let Err(e) = detector.scan(false).await {
+        log::warn!("Unable to list the objects of '{}': {}", detector.client, e);
+    }
+    log::debug!(
+        "Change detection started on '{}' with {} objects.",
+        detector.client,
+        detector.objects.len()
+    );
+
+    match config.source {
+        ChangeSource::Poll(period) => {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = detector.scan(true).await {
+                    log::warn!(
+                        "Error while detecting changes on '{}': {}",
+                        detector.client,
+                        e
+                    );
+                }
+            }
+        }
+        ChangeSource::Events(addr, token) => {
+            let (sender, mut receiver) = unbounded_channel();
+            let bucket = detector.client.to_string();
+            let token = Arc::new(token);
+            let make_service = make_service_fn(move |_| {
+                let sender = sender.clone();
+                let bucket = bucket.clone();
+                let token = token.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        handle_notification(request, bucket.clone(), token.clone(), sender.clone())
+                    }))
+                }
+            });
+            let server = match hyper::Server::try_bind(&addr) {
+                Ok(server) => server.serve(make_service),
+                Err(e) => {
+                    log::warn!("Unable to listen to event notifications on {}: {}", addr, e);
+                    return;
+                }
+            };
+            // The listener is polled by this task so that it stops when the task is aborted.
+            let events = async {
+                while let Some(event) = receiver.recv().await {
+                    detector.apply(event).await;
+                }
+            };
+            tokio::select! {
+                Err(e) = server => {
+                    log::warn!("Event notifications listener on {} failed: {}", addr, e);
+                }
+                _ = events => {}
+            }
+        }
+    }
+}
+
+/// Forwards the object events of an S3 event notification posted to the webhook, if it holds the
+/// expected token.
+async fn handle_notification(
+    request: Request<Body>,
+    bucket: String,
+    token: Arc<Option<String>>,
+    sender: UnboundedSender<ObjectEvent>,
+) -> Result<Response<Body>, Infallible> {
+    if let Some(token) = token.as_ref() {
+        let authorization = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|authorization| authorization.to_str().ok());
+        if !is_authorized(authorization, token) {
+            log::debug!("Rejecting unauthenticated event notification.");
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            return Ok(response);
+        }
+    }
+    let status = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => match parse_notification(&body, &bucket) {
+            Ok(events) => {
+                for event in events {
+                    let _ = sender.send(event);
+                }
+                StatusCode::OK
+            }
+            Err(e) => {
+                log::debug!("Ignoring invalid event notification: {}", e);
+                StatusCode::BAD_REQUEST
+            }
+        },
+        Err(_) => StatusCode::BAD_REQUEST,
+    };
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+/// Whether the `Authorization` header of a notification holds the token, either as is or as a
+/// bearer token (as sent by the webhook targets of MinIO and of most S3 servers).
+fn is_authorized(authorization: Option<&str>, token: &str) -> bool {
+    let authorization = match authorization {
+        Some(authorization) => authorization,
+        None => return false,
+    };
+    let credentials = authorization
+        .strip_prefix("Bearer ")
+        .unwrap_or(authorization)
+        .trim();
+    // Compared in constant time so that the token can't be guessed from the response delays.
+    credentials.len() == token.len()
+        && credentials
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Parses the records of an S3 event notification concerning the bucket specified.
+fn parse_notification(body: &[u8], bucket: &str) -> ZResult<Vec<ObjectEvent>> {
+    let notification: Value = serde_json::from_slice(body)?;
+    let records = match notification.get("Records") {
+        Some(Value::Array(records)) => records,
+        // Test events sent when configuring the notifications hold no records.
+        _ => return Ok(vec![]),
+    };
+    let mut events = vec![];
+    for record in records {
+        let s3 = &record["s3"];
+        if s3["bucket"]["name"].as_str() != Some(bucket) {
+            continue;
+        }
+        let key = s3["object"]["key"]
+            .as_str()
+            .and_then(percent_decode)
+            .ok_or_else(|| zerror!("Invalid object key in event record"))?;
+        let event_name = record["eventName"].as_str().unwrap_or_default();
+        let event_name = event_name.trim_start_matches("s3:");
+        if event_name.starts_with("ObjectCreated:") {
+            let etag = s3["object"]["eTag"].as_str().map(|etag| etag.to_owned());
+            events.push(ObjectEvent::Created { key, etag });
+        } else if event_name.starts_with("ObjectRemoved:") {
+            events.push(ObjectEvent::Removed { key });
+        }
+    }
+    Ok(events)
+}
+
+impl ChangeDetector {
+    /// Lists the bucket and publishes the objects created, modified or removed since the previous
+    /// scan, if `publish` is true.
+    async fn scan(&mut self, publish: bool) -> ZResult<()> {
+        let mut listed = HashSet::new();
+        for object in self.client.list_objects_in_bucket().await? {
+            let key = match object.key() {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+            let etag = object.e_tag().map(|etag| etag.to_owned());
+            let last_modified = object
+                .last_modified()
+                .map(|date_time| (date_time.secs(), date_time.subsec_nanos()));
+            listed.insert(key.to_owned());
+            let unchanged = self.objects.get(&key).map_or(false, |state| {
+                state.etag == etag && state.last_modified == last_modified
+            });
+            if unchanged {
+                continue;
+            }
+            let foreign = if publish {
+                match self.publish_put(&key).await {
+                    Ok(foreign) => foreign,
+                    Err(e) => {
+                        // The object is not recorded, hence published on the next scan.
+                        log::warn!(
+                            "Unable to publish object '{}' of '{}': {}",
+                            key,
+                            self.client,
+                            e
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                match self.client.get_head_object(&key).await {
+                    Ok(head) => !has_timestamp(head.metadata()),
+                    Err(e) => {
+                        // Assumed foreign, so that its deletion is published.
+                        log::warn!(
+                            "Unable to check object '{}' of '{}': {}",
+                            key,
+                            self.client,
+                            e
+                        );
+                        true
+                    }
+                }
+            };
+            self.objects.insert(
+                key,
+                ObjectState {
+                    etag,
+                    last_modified,
+                    foreign,
+                },
+            );
+        }
+
+        let removed: Vec<String> = self
+            .objects
+            .keys()
+            .filter(|key| !listed.contains(*key))
+            .cloned()
+            .collect();
+        for key in removed {
+            if let Some(state) = self.objects.remove(&key) {
+                if publish && state.foreign {
+                    self.publish_delete(&key).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the change notified by an S3 event. Failures are logged, the event being lost.
+    async fn apply(&mut self, event: ObjectEvent) {
+        match event {
+            ObjectEvent::Created { key, etag } => match self.publish_put(&key).await {
+                Ok(foreign) => {
+                    self.objects.insert(
+                        key,
+                        ObjectState {
+                            etag,
+                            last_modified: None,
+                            foreign,
+                        },
+                    );
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Unable to publish object '{}' of '{}': {}",
+                        key,
+                        self.client,
+                        e
+                    )
+                }
+            },
+            ObjectEvent::Removed { key } => {
+                if let Some(state) = self.objects.remove(&key) {
+                    if state.foreign {
+                        self.publish_delete(&key).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Publishes the content of the object if it wasn't written through zenoh, returning whether
+    /// the object is foreign. Only the head of the objects written through zenoh is retrieved.
+    async fn publish_put(&self, object_key: &str) -> ZResult<bool> {
+        let head = self.client.get_head_object(object_key).await?;
+        if has_timestamp(head.metadata()) {
+            return Ok(false);
+        }
+        let key_expr = match self.key_expr(object_key) {
+            Some(key_expr) => key_expr,
+            None => return Ok(true),
+        };
+        let output = self.client.get_object(object_key).await?;
+        if has_timestamp(output.metadata()) {
+            // Overwritten through zenoh since its head was retrieved.
+            return Ok(false);
+        }
+        let encoding = output
+            .content_encoding()
+            .or_else(|| output.content_type())
+            .and_then(|encoding| Encoding::try_from(encoding.to_owned()).ok());
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .map(|data| data.into_bytes())
+            .map_err(|e| zerror!("Couldn't read the contents of '{object_key}': {e}"))?;
+        self.published
+            .insert(object_key.trim_start_matches('/'), &bytes);
+        let value = zenoh::value::Value::from(Vec::from(bytes));
+        let value = match encoding {
+            Some(encoding) => value.encoding(encoding),
+            None => value,
+        };
+        log::debug!("Publishing externally written object '{}'.", key_expr);
+        self.session.put(key_expr, value).res().await?;
+        Ok(true)
+    }
+
+    async fn publish_delete(&self, object_key: &str) {
+        if let Some(key_expr) = self.key_expr(object_key) {
+            log::debug!(
+                "Publishing deletion of externally written object '{}'.",
+                key_expr
+            );
+            if let Err(e) = self.session.delete(&key_expr).res().await {
+                log::warn!("Unable to publish deletion of '{}': {}", key_expr, e);
+            }
+        }
+    }
+
+    /// Returns the key expression associated to an object of the bucket, if valid.
+    fn key_expr(&self, object_key: &str) -> Option<OwnedKeyExpr> {
+        let key = object_key.trim_start_matches('/');
+        let key_expr = match (&self.path_prefix, key == NONE_KEY) {
+            (Some(prefix), true) => prefix.to_owned(),
+            (Some(prefix), false) => format!("{prefix}/{key}"),
+            (None, true) => return None,
+            (None, false) => key.to_owned(),
+        };
+        OwnedKeyExpr::try_from(key_expr)
+            .map_err(|e| log::debug!("Not publishing object '{}': {}", object_key, e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_must_hold_the_token() {
+        assert!(is_authorized(Some("secret"), "secret"));
+        assert!(is_authorized(Some("Bearer secret"), "secret"));
+        assert!(!is_authorized(Some("Bearer other"), "secret"));
+        assert!(!is_authorized(Some("secre"), "secret"));
+        assert!(!is_authorized(None, "secret"));
+    }
+
+    #[test]
+    fn published_objects_are_taken_once_with_their_content() {
+        let published = PublishedObjects::default();
+        published.insert("a/b", b"foreign");
+        assert!(!published.take("a/b", b"zenoh"));
+        assert!(!published.take("a/c", b"foreign"));
+        assert!(published.take("a/b", b"foreign"));
+        assert!(!published.take("a/b", b"foreign"));
+    }
+}
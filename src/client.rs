@@ -12,30 +12,130 @@
 //   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
 //
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
 use aws_sdk_s3::model::{
-    BucketLocationConstraint, CreateBucketConfiguration, Delete, Object, ObjectIdentifier,
+    BucketLifecycleConfiguration, BucketLocationConstraint, BucketVersioningStatus,
+    ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, CreateBucketConfiguration, Delete,
+    DeleteMarkerEntry, LifecycleRule, MetadataDirective, Object, ObjectIdentifier, ObjectVersion,
+    ServerSideEncryptionByDefault, ServerSideEncryptionConfiguration, ServerSideEncryptionRule,
+    StorageClass, Tagging, VersioningConfiguration,
 };
 use aws_sdk_s3::output::{
     CreateBucketOutput, DeleteObjectOutput, DeleteObjectsOutput, GetObjectOutput, HeadObjectOutput,
 };
+use aws_sdk_s3::types::SdkError;
 use aws_sdk_s3::{output::PutObjectOutput, types::ByteStream, Client};
 use aws_sdk_s3::{Credentials, Endpoint, Region};
 use aws_smithy_client::hyper_ext;
+use aws_smithy_types::retry::{ProvideErrorKind, RetryConfig};
+use tracing::field::Empty;
+use tracing::Instrument;
 use zenoh::value::Value;
 use zenoh::Result as ZResult;
 use zenoh_buffers::buffer::SplitBuffer;
 use zenoh_core::zerror;
 
-use crate::config::TlsClientConfig;
+use crate::bucket_creation::BucketCreationConfig;
+use crate::config::{TlsClientConfig, TLS_ROOT_CA_CERTIFICATE_FILE};
+use crate::error::{S3Error, S3ErrorKind};
+use crate::lifecycle::LifecycleRuleConfig;
+use crate::metrics::Metrics;
+use crate::rate_limit::{Operation, RequestLimiter};
+use crate::retention::ObjectLock;
+use crate::utils::{percent_encode, LatencyRecorder};
+use crate::{MANIFEST_KEY, MAX_DELETE_OBJECTS_BATCH};
+
+const TOOL_PROVIDER: &str = "zenoh-s3-backend-tool";
+
+// Maximum amount of attempts of a request failing with a throttling or transient error.
+const MAX_ATTEMPTS: u32 = 3;
+
+// Delay before the first retry of a request, doubled for each subsequent retry.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Optional settings of the objects written with [S3Client::put_object].
+#[derive(Default)]
+pub(crate) struct PutObjectOptions {
+    pub storage_class: Option<StorageClass>,
+    /// Tags of the object, encoded as URL query parameters.
+    pub tagging: Option<String>,
+    pub object_lock: Option<ObjectLock>,
+}
+
+/// Location of the bucket of a storage and credentials to access it, used by the tools operating
+/// on a bucket outside of a zenoh router (see the `zenoh-backend-s3-tool` binary).
+pub struct BucketLocation {
+    pub bucket: String,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Certificate authority to authenticate the S3 server with TLS, in addition to the Web PKI
+    /// ones, as the `tls.root_ca_certificate_file` volume property.
+    pub root_ca_certificate_file: Option<String>,
+}
+
+impl BucketLocation {
+    pub(crate) async fn client(&self) -> ZResult<S3Client> {
+        let tls_config = self
+            .root_ca_certificate_file
+            .as_ref()
+            .map(|file| {
+                let mut tls_config = serde_json::Map::new();
+                tls_config.insert(
+                    "private".to_owned(),
+                    serde_json::json!({ TLS_ROOT_CA_CERTIFICATE_FILE: file }),
+                );
+                TlsClientConfig::new(&tls_config)
+            })
+            .transpose()?;
+        Ok(S3Client::new(
+            Credentials::new(
+                self.access_key.to_owned(),
+                self.secret_key.to_owned(),
+                None,
+                None,
+                TOOL_PROVIDER,
+            ),
+            self.bucket.to_owned(),
+            self.region.to_owned(),
+            self.endpoint.to_owned(),
+            tls_config,
+        )
+        .await)
+    }
+}
+
+/// Size of the largest object which can be copied with a single CopyObject request (5 GiB).
+const MAX_COPY_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+/// Size of the parts of the multipart copies, unless more than [MAX_UPLOAD_PARTS] are needed.
+const COPY_PART_SIZE: i64 = 512 * 1024 * 1024;
+const MAX_UPLOAD_PARTS: i64 = 10_000;
+
+/// Byte ranges, inclusive, of the parts copying an object of the given size.
+fn copy_part_ranges(size: i64) -> Vec<(i64, i64)> {
+    let part_size = COPY_PART_SIZE.max((size + MAX_UPLOAD_PARTS - 1) / MAX_UPLOAD_PARTS);
+    (0..size)
+        .step_by(part_size as usize)
+        .map(|first| (first, (first + part_size).min(size) - 1))
+        .collect()
+}
 
 /// Client to communicate with the S3 storage.
 pub(crate) struct S3Client {
     client: Client,
     bucket: String,
     region: Option<String>,
+    // Endpoint configured for the client, if any.
+    endpoint: Option<String>,
+    metrics: Arc<Metrics>,
+    // Bounds the amount and the rate of the requests issued through the client, if any.
+    limiter: Option<Arc<RequestLimiter>>,
 }
 
 impl S3Client {
@@ -58,8 +158,10 @@ impl S3Client {
         endpoint: Option<String>,
         tls_config: Option<TlsClientConfig>,
     ) -> Self {
-        let mut config_loader =
-            aws_config::ConfigLoader::default().credentials_provider(credentials);
+        // Requests are retried by [S3Client::send], so that retries are accounted for.
+        let mut config_loader = aws_config::ConfigLoader::default()
+            .credentials_provider(credentials)
+            .retry_config(RetryConfig::disabled());
 
         config_loader = match region {
             Some(ref region) => config_loader.region(Region::new(region.to_owned())),
@@ -73,7 +175,7 @@ impl S3Client {
             }
         };
 
-        config_loader = match endpoint {
+        config_loader = match endpoint.as_ref() {
             Some(endpoint) => config_loader.endpoint_resolver(Endpoint::immutable(
                 endpoint.parse().expect("Invalid endpoint: "),
             )),
@@ -94,68 +196,568 @@ impl S3Client {
             Client::new(config)
         };
 
+        let metrics = Metrics::new(None, bucket.to_owned());
         S3Client {
             client,
             bucket,
             region,
+            endpoint,
+            metrics,
+            limiter: None,
         }
     }
 
+    /// Issues the requests of the client within the limits of the [RequestLimiter] specified.
+    pub fn with_limiter(mut self, limiter: Arc<RequestLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Reports the metrics of the client under the name of the storage it belongs to.
+    pub fn with_storage_name(mut self, name: String) -> Self {
+        self.metrics = Metrics::new(Some(name), self.bucket.to_owned());
+        self
+    }
+
+    /// Region the requests are signed for, as set in the configuration the client was built with.
+    /// None if no region is configured, e.g. for MinIO servers.
+    pub fn signing_region(&self) -> Option<String> {
+        self.client
+            .conf()
+            .region()
+            .map(|region| region.as_ref().to_owned())
+            .filter(|region| !region.is_empty())
+    }
+
+    /// Endpoint the requests are sent to: either the configured one or "default (AWS)", the
+    /// endpoint then being resolved by the SDK from the region (see [S3Client::signing_region])
+    /// and its settings, e.g. FIPS or dual-stack.
+    pub fn endpoint(&self) -> String {
+        self.endpoint
+            .to_owned()
+            .unwrap_or_else(|| "default (AWS)".to_owned())
+    }
+
+    /// Metrics of the requests issued by this client.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Accounts for the payload of a retrieved object in the metrics of the client and in the
+    /// current span.
+    fn received(&self, output: GetObjectOutput) -> GetObjectOutput {
+        let bytes = output.content_length().max(0) as u64;
+        self.metrics.add_bytes_in(bytes);
+        tracing::Span::current().record("bytes", bytes);
+        output
+    }
+
+    /// Sends the request built by [request], retrying it on throttling and transient errors, and
+    /// records its outcome in the metrics of the client under the [operation] type. Each attempt
+    /// waits for a permit of the limiter of the client, if any, released during the backoffs. The
+    /// request is traced in a `s3_request` span, carrying the S3 request ID on errors.
+    async fn send<T, E, F, Fut>(
+        &self,
+        operation: &'static str,
+        mut request: F,
+    ) -> Result<T, S3Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E>>>,
+        E: ProvideErrorKind + std::error::Error + 'static,
+    {
+        let span = tracing::debug_span!(
+            "s3_request",
+            bucket = %self.bucket,
+            operation,
+            retries = Empty,
+            latency_ms = Empty,
+            error_kind = Empty,
+            error_code = Empty,
+            request_id = Empty,
+        );
+        async {
+            let latency = LatencyRecorder::start();
+            let mut retries = 0;
+            loop {
+                let permit = match &self.limiter {
+                    Some(limiter) => Some(limiter.acquire(Operation::of(operation)).await),
+                    None => None,
+                };
+                let result = request().await.map_err(S3Error::from);
+                drop(permit);
+                let error = match &result {
+                    Err(err) if err.kind() == S3ErrorKind::NotModified => None,
+                    Err(err) => Some(err),
+                    Ok(_) => None,
+                };
+                if let Some(err) = error {
+                    if retries + 1 < MAX_ATTEMPTS && err.is_retryable() {
+                        retries += 1;
+                        log::debug!(
+                            "Retrying {} request on '{}' ({}/{}): {}",
+                            operation,
+                            self,
+                            retries,
+                            MAX_ATTEMPTS - 1,
+                            err
+                        );
+                        tokio::time::sleep(INITIAL_BACKOFF * 2u32.pow(retries - 1)).await;
+                        continue;
+                    }
+                    let span = tracing::Span::current();
+                    span.record("error_kind", err.kind().as_str());
+                    span.record("error_code", err.code());
+                    span.record("request_id", err.request_id());
+                    tracing::debug!(error = %err, "S3 request failed");
+                }
+                tracing::Span::current().record("retries", retries);
+                self.metrics
+                    .record(operation, latency.elapsed(), retries, error);
+                return result;
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
     /// Retrieves the object associated to the [key] specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key, bytes = Empty))]
     pub async fn get_object(&self, key: &str) -> ZResult<GetObjectOutput> {
-        Ok(self
-            .client
-            .get_object()
-            .bucket(&self.bucket)
-            .key(key.to_string())
-            .send()
-            .await?)
+        let output = self
+            .send("get", || {
+                self.client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(key.to_string())
+                    .send()
+            })
+            .await?;
+        Ok(self.received(output))
+    }
+
+    /// Retrieves the object associated to the [key] specified unless its ETag still matches the
+    /// [etag] specified, in which case None is returned.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key, bytes = Empty))]
+    pub async fn get_object_if_none_match(
+        &self,
+        key: &str,
+        etag: &str,
+    ) -> ZResult<Option<GetObjectOutput>> {
+        let result = self
+            .send("get", || {
+                self.client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(key.to_string())
+                    .if_none_match(etag)
+                    .send()
+            })
+            .await;
+        match result {
+            Ok(output) => Ok(Some(self.received(output))),
+            Err(err) if err.kind() == S3ErrorKind::NotModified => Ok(None),
+            Err(err) => Err(err.into()),
+        }
     }
 
     /// Retrieves the head object (the header of the object without its actual payload) associated
     /// to the [key] specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
     pub async fn get_head_object(&self, key: &str) -> ZResult<HeadObjectOutput> {
-        Ok(self
-            .client
-            .head_object()
-            .bucket(&self.bucket)
-            .key(key.to_string())
-            .send()
-            .await?)
+        let result = self
+            .send("head", || {
+                self.client
+                    .head_object()
+                    .bucket(&self.bucket)
+                    .key(key.to_string())
+                    .send()
+            })
+            .await;
+        Ok(self.check_head_result(result).await?)
+    }
+
+    /// The responses to HEAD requests have no body, hence no error code telling a missing object
+    /// from a missing bucket: on a 404, the bucket is checked so that a missing bucket is reported
+    /// as such rather than as an absent object.
+    async fn check_head_result<T>(&self, result: Result<T, S3Error>) -> Result<T, S3Error> {
+        match result {
+            Err(err) if err.kind() == S3ErrorKind::NotFound => {
+                let bucket = self
+                    .send("head", || {
+                        self.client.head_bucket().bucket(&self.bucket).send()
+                    })
+                    .await;
+                match bucket {
+                    Err(bucket_err) if bucket_err.kind() == S3ErrorKind::NotFound => {
+                        self.metrics.record_bucket_missing();
+                        Err(S3Error::new(
+                            S3ErrorKind::BucketMissing,
+                            format!("Bucket '{}' doesn't exist", self.bucket),
+                        ))
+                    }
+                    _ => Err(err),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Retrieves a specific version of the object associated to the [key] specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key, bytes = Empty))]
+    pub async fn get_object_version(
+        &self,
+        key: &str,
+        version_id: &str,
+    ) -> ZResult<GetObjectOutput> {
+        let output = self
+            .send("get", || {
+                self.client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(key.to_string())
+                    .version_id(version_id)
+                    .send()
+            })
+            .await?;
+        Ok(self.received(output))
+    }
+
+    /// Retrieves the head object of a specific version of the object associated to the [key]
+    /// specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
+    pub async fn get_head_object_version(
+        &self,
+        key: &str,
+        version_id: &str,
+    ) -> ZResult<HeadObjectOutput> {
+        let result = self
+            .send("head", || {
+                self.client
+                    .head_object()
+                    .bucket(&self.bucket)
+                    .key(key.to_string())
+                    .version_id(version_id)
+                    .send()
+            })
+            .await;
+        Ok(self.check_head_result(result).await?)
+    }
+
+    /// Lists all the versions and delete markers of the object associated to the [key]
+    /// specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
+    pub async fn list_object_versions(
+        &self,
+        key: &str,
+    ) -> ZResult<(Vec<ObjectVersion>, Vec<DeleteMarkerEntry>)> {
+        let (mut versions, mut delete_markers) = self.list_versions(Some(key)).await?;
+        versions.retain(|version| version.key() == Some(key));
+        delete_markers.retain(|delete_marker| delete_marker.key() == Some(key));
+        Ok((versions, delete_markers))
+    }
+
+    /// Lists all the versions and delete markers of the objects of the bucket, per object name,
+    /// except for the manifest written by the `snapshot` closure strategy.
+    pub async fn list_bucket_versions(
+        &self,
+    ) -> ZResult<BTreeMap<String, (Vec<ObjectVersion>, Vec<DeleteMarkerEntry>)>> {
+        let (versions, delete_markers) = self.list_versions(None).await?;
+        let mut objects: BTreeMap<String, (Vec<ObjectVersion>, Vec<DeleteMarkerEntry>)> =
+            BTreeMap::new();
+        for version in versions {
+            if let Some(key) = version.key().filter(|key| *key != MANIFEST_KEY) {
+                objects.entry(key.to_owned()).or_default().0.push(version);
+            }
+        }
+        for delete_marker in delete_markers {
+            if let Some(key) = delete_marker.key().filter(|key| *key != MANIFEST_KEY) {
+                objects
+                    .entry(key.to_owned())
+                    .or_default()
+                    .1
+                    .push(delete_marker);
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Lists the versions and delete markers of the objects whose name starts with the prefix
+    /// specified, or of all the objects of the bucket, newest first for each object.
+    async fn list_versions(
+        &self,
+        prefix: Option<&str>,
+    ) -> ZResult<(Vec<ObjectVersion>, Vec<DeleteMarkerEntry>)> {
+        let mut versions = vec![];
+        let mut delete_markers = vec![];
+        let mut key_marker = None;
+        let mut version_id_marker = None;
+        loop {
+            let response = self
+                .send("list", || {
+                    self.client
+                        .list_object_versions()
+                        .bucket(&self.bucket)
+                        .set_prefix(prefix.map(|prefix| prefix.to_owned()))
+                        .set_key_marker(key_marker.to_owned())
+                        .set_version_id_marker(version_id_marker.to_owned())
+                        .send()
+                })
+                .await?;
+            versions.extend_from_slice(response.versions().unwrap_or_default());
+            delete_markers.extend_from_slice(response.delete_markers().unwrap_or_default());
+            if !response.is_truncated() {
+                break;
+            }
+            key_marker = response.next_key_marker().map(|marker| marker.to_owned());
+            version_id_marker = response
+                .next_version_id_marker()
+                .map(|marker| marker.to_owned());
+        }
+        Ok((versions, delete_markers))
     }
 
     /// Performs a put operation on the storage on the key specified (which corresponds to the
     /// name of the file to be created) with the [Sample] provided.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key, bytes = Empty))]
     pub async fn put_object(
         &self,
         key: String,
         value: Value,
         metadata: Option<HashMap<String, String>>,
+        options: PutObjectOptions,
     ) -> ZResult<PutObjectOutput> {
-        let body = ByteStream::from(value.payload.contiguous().to_vec());
+        let body = value.payload.contiguous().to_vec();
+        self.metrics.add_bytes_out(body.len() as u64);
+        tracing::Span::current().record("bytes", body.len());
+        let object_lock = options.object_lock.as_ref();
         Ok(self
-            .client
-            .put_object()
-            .bucket(self.bucket.to_owned())
-            .key(key)
-            .body(body)
-            .set_content_encoding(Some(value.encoding.to_string()))
-            .set_metadata(metadata)
-            .send()
+            .send("put", || {
+                self.client
+                    .put_object()
+                    .bucket(self.bucket.to_owned())
+                    .key(&key)
+                    .body(ByteStream::from(body.to_owned()))
+                    .set_content_encoding(Some(value.encoding.to_string()))
+                    .set_metadata(metadata.to_owned())
+                    .set_storage_class(options.storage_class.to_owned())
+                    .set_tagging(options.tagging.to_owned())
+                    .set_object_lock_mode(object_lock.map(ObjectLock::mode))
+                    .set_object_lock_retain_until_date(
+                        object_lock.map(ObjectLock::retain_until_date),
+                    )
+                    .set_object_lock_legal_hold_status(
+                        object_lock.and_then(ObjectLock::legal_hold_status),
+                    )
+                    // S3 requires a checksum of the objects written with Object Lock settings.
+                    .set_checksum_algorithm(object_lock.map(|_| ChecksumAlgorithm::Crc32))
+                    .send()
+            })
             .await?)
     }
 
+    /// Replaces the metadata of the object associated to the key specified by copying the object
+    /// onto itself, keeping its content encoding, content type and storage class as described by
+    /// its head object.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
+    pub async fn replace_object_metadata(
+        &self,
+        key: &str,
+        head: &HeadObjectOutput,
+        metadata: HashMap<String, String>,
+    ) -> ZResult<()> {
+        self.send("copy", || {
+            self.client
+                .copy_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .copy_source(format!("{}/{}", self.bucket, percent_encode(key)))
+                .metadata_directive(MetadataDirective::Replace)
+                .set_metadata(Some(metadata.to_owned()))
+                .set_content_encoding(head.content_encoding().map(|x| x.to_owned()))
+                .set_content_type(head.content_type().map(|x| x.to_owned()))
+                .set_storage_class(head.storage_class().cloned())
+                .send()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Copies the object associated to the [key] specified, of [size] bytes, to the [target_key]
+    /// of the [target_bucket], along with its metadata. Objects larger than what a single
+    /// CopyObject request supports ([MAX_COPY_OBJECT_SIZE]) are copied with a multipart upload.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
+    pub async fn copy_object_to(
+        &self,
+        key: &str,
+        size: i64,
+        target_bucket: &str,
+        target_key: &str,
+    ) -> ZResult<()> {
+        if size > MAX_COPY_OBJECT_SIZE {
+            return self
+                .multipart_copy_object_to(key, size, target_bucket, target_key)
+                .await;
+        }
+        self.send("copy", || {
+            self.client
+                .copy_object()
+                .bucket(target_bucket)
+                .key(target_key)
+                .copy_source(format!("{}/{}", self.bucket, percent_encode(key)))
+                .send()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Copies the object with a multipart upload whose parts are copied from ranges of the
+    /// object. The metadata, the content type and encoding, the storage class and the tags of the
+    /// object are set on the upload.
+    async fn multipart_copy_object_to(
+        &self,
+        key: &str,
+        size: i64,
+        target_bucket: &str,
+        target_key: &str,
+    ) -> ZResult<()> {
+        let head = self.get_head_object(key).await?;
+        // Unlike CopyObject, CreateMultipartUpload doesn't carry over the tags of the source.
+        let tagging = self
+            .send("get_tagging", || {
+                self.client
+                    .get_object_tagging()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .send()
+            })
+            .await?
+            .tag_set()
+            .unwrap_or_default()
+            .iter()
+            .map(|tag| {
+                format!(
+                    "{}={}",
+                    percent_encode(tag.key().unwrap_or_default()),
+                    percent_encode(tag.value().unwrap_or_default())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let upload = self
+            .send("copy", || {
+                self.client
+                    .create_multipart_upload()
+                    .bucket(target_bucket)
+                    .key(target_key)
+                    .set_metadata(head.metadata().cloned())
+                    .set_content_encoding(head.content_encoding().map(|x| x.to_owned()))
+                    .set_content_type(head.content_type().map(|x| x.to_owned()))
+                    .set_storage_class(head.storage_class().cloned())
+                    .set_tagging(Some(tagging.to_owned()).filter(|tagging| !tagging.is_empty()))
+                    .send()
+            })
+            .await?;
+        let upload_id = upload.upload_id().ok_or_else(|| {
+            zerror!("No upload ID returned to copy '{key}' into '{target_bucket}'")
+        })?;
+        let copy_source = format!("{}/{}", self.bucket, percent_encode(key));
+
+        let mut parts = vec![];
+        for (index, (first, last)) in copy_part_ranges(size).into_iter().enumerate() {
+            let part_number = index as i32 + 1;
+            let result = self
+                .send("copy", || {
+                    self.client
+                        .upload_part_copy()
+                        .bucket(target_bucket)
+                        .key(target_key)
+                        .upload_id(upload_id)
+                        .part_number(part_number)
+                        .copy_source(&copy_source)
+                        .copy_source_range(format!("bytes={first}-{last}"))
+                        .send()
+                })
+                .await;
+            let e_tag = match result {
+                Ok(output) => output
+                    .copy_part_result()
+                    .and_then(|result| result.e_tag())
+                    .map(|e_tag| e_tag.to_owned()),
+                Err(e) => {
+                    // The parts copied so far would be billed until the upload is aborted.
+                    let _ = self
+                        .send("delete", || {
+                            self.client
+                                .abort_multipart_upload()
+                                .bucket(target_bucket)
+                                .key(target_key)
+                                .upload_id(upload_id)
+                                .send()
+                        })
+                        .await;
+                    return Err(e.into());
+                }
+            };
+            parts.push(
+                CompletedPart::builder()
+                    .set_e_tag(e_tag)
+                    .part_number(part_number)
+                    .build(),
+            );
+        }
+
+        let completed = CompletedMultipartUpload::builder()
+            .set_parts(Some(parts))
+            .build();
+        self.send("copy", || {
+            self.client
+                .complete_multipart_upload()
+                .bucket(target_bucket)
+                .key(target_key)
+                .upload_id(upload_id)
+                .multipart_upload(completed.to_owned())
+                .send()
+        })
+        .await?;
+        log::debug!(
+            "Copied '{}' ({} bytes) into '{}' with a multipart upload.",
+            key,
+            size,
+            target_bucket
+        );
+        Ok(())
+    }
+
     /// Performs a DELETE operation on the key specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
     pub async fn delete_object(&self, key: String) -> ZResult<DeleteObjectOutput> {
         Ok(self
-            .client
-            .delete_object()
-            .bucket(self.bucket.to_owned())
-            .key(key)
-            .send()
+            .send("delete", || {
+                self.client
+                    .delete_object()
+                    .bucket(self.bucket.to_owned())
+                    .key(&key)
+                    .send()
+            })
             .await?)
     }
 
+    /// Permanently deletes a specific version of the object associated to the [key] specified.
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key))]
+    pub async fn remove_object_version(&self, key: &str, version_id: &str) -> ZResult<()> {
+        self.send("delete", || {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .version_id(version_id)
+                .send()
+        })
+        .await?;
+        Ok(())
+    }
+
     /// Deletes the specified objects from the bucket.
     pub async fn delete_objects_in_bucket(
         &self,
@@ -181,23 +783,30 @@ impl S3Client {
             .build();
 
         Ok(self
-            .client
-            .delete_objects()
-            .bucket(self.bucket.to_owned())
-            .delete(delete)
-            .send()
+            .send("delete", || {
+                self.client
+                    .delete_objects()
+                    .bucket(self.bucket.to_owned())
+                    .delete(delete.to_owned())
+                    .send()
+            })
             .await?)
     }
 
-    /// Asyncronically creates the bucket associated to this client upon construction on a new
-    /// tokio runtime.
+    /// Asyncronically creates the bucket associated to this client upon construction.
     /// Returns:
     /// - Ok(Some(CreateBucketOutput)) in case the bucket was successfully created
     /// - Ok(Some(None)) in case the `reuse_bucket` parameter is true and the bucket already exists
     ///     and is owned by you
     /// - Error in any other case
-    #[tokio::main]
-    pub async fn create_bucket(&self, reuse_bucket: bool) -> ZResult<Option<CreateBucketOutput>> {
+    ///
+    /// The object lock, ACL and object ownership settings of [options] are applied on creation,
+    /// see [S3Client::configure_bucket] for the other ones.
+    pub async fn create_bucket(
+        &self,
+        reuse_bucket: bool,
+        options: &BucketCreationConfig,
+    ) -> ZResult<Option<CreateBucketOutput>> {
         let constraint = self
             .region
             .as_ref()
@@ -206,51 +815,368 @@ impl S3Client {
             .set_location_constraint(constraint)
             .build();
         let result = self
-            .client
-            .create_bucket()
-            .create_bucket_configuration(cfg)
-            .bucket(self.bucket.to_owned())
-            .send()
+            .send("create_bucket", || {
+                self.client
+                    .create_bucket()
+                    .create_bucket_configuration(cfg.to_owned())
+                    .bucket(self.bucket.to_owned())
+                    .set_object_lock_enabled_for_bucket(options.object_lock.then_some(true))
+                    .set_acl(options.acl.to_owned())
+                    .set_object_ownership(options.object_ownership.to_owned())
+                    .send()
+            })
             .await;
 
         match result {
             Ok(output) => Ok(Some(output)),
-            Err(aws_sdk_s3::types::SdkError::ServiceError { err, raw }) => {
-                if err.is_bucket_already_owned_by_you() && reuse_bucket {
-                    return Ok(None);
-                };
-                Err(zerror!("Couldn't associate bucket '{self}': {raw:?}").into())
-            }
-            Err(err) => {
-                Err(zerror!("Couldn't create or associate bucket '{self}': {err:?}.").into())
-            }
+            Err(err) if err.kind() == S3ErrorKind::BucketAlreadyOwned && reuse_bucket => Ok(None),
+            Err(err) => Err(err
+                .context(format!("Couldn't create or associate bucket '{self}'"))
+                .into()),
+        }
+    }
+
+    /// Applies the public access block, default encryption and tags settings of [options] on the
+    /// bucket.
+    pub async fn configure_bucket(&self, options: &BucketCreationConfig) -> ZResult<()> {
+        if let Some(public_access_block) = &options.public_access_block {
+            self.send("configure_bucket", || {
+                self.client
+                    .put_public_access_block()
+                    .bucket(&self.bucket)
+                    .public_access_block_configuration(public_access_block.to_owned())
+                    .send()
+            })
+            .await?;
+        }
+        if let Some(encryption) = &options.encryption {
+            let default = ServerSideEncryptionByDefault::builder()
+                .sse_algorithm(encryption.algorithm.to_owned())
+                .set_kms_master_key_id(encryption.kms_key_id.to_owned())
+                .build();
+            let rule = ServerSideEncryptionRule::builder()
+                .apply_server_side_encryption_by_default(default)
+                .bucket_key_enabled(encryption.bucket_key)
+                .build();
+            self.send("configure_bucket", || {
+                self.client
+                    .put_bucket_encryption()
+                    .bucket(&self.bucket)
+                    .server_side_encryption_configuration(
+                        ServerSideEncryptionConfiguration::builder()
+                            .rules(rule.to_owned())
+                            .build(),
+                    )
+                    .send()
+            })
+            .await?;
+        }
+        if !options.tags.is_empty() {
+            self.send("configure_bucket", || {
+                self.client
+                    .put_bucket_tagging()
+                    .bucket(&self.bucket)
+                    .tagging(
+                        Tagging::builder()
+                            .set_tag_set(Some(options.tags.to_owned()))
+                            .build(),
+                    )
+                    .send()
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the region of the bucket, None standing for the default region (us-east-1). The
+    /// legacy `EU` location constraint is reported as the region it stands for (eu-west-1).
+    pub async fn get_bucket_region(&self) -> ZResult<Option<String>> {
+        let response = self
+            .send("get_bucket_configuration", || {
+                self.client
+                    .get_bucket_location()
+                    .bucket(&self.bucket)
+                    .send()
+            })
+            .await?;
+        Ok(response
+            .location_constraint()
+            .map(|constraint| match constraint {
+                BucketLocationConstraint::Eu => "eu-west-1".to_owned(),
+                constraint => constraint.as_str().to_owned(),
+            })
+            .filter(|region| !region.is_empty()))
+    }
+
+    /// Enables the versioning of the bucket.
+    pub async fn enable_bucket_versioning(&self) -> ZResult<()> {
+        let versioning = VersioningConfiguration::builder()
+            .status(BucketVersioningStatus::Enabled)
+            .build();
+        self.send("configure_bucket", || {
+            self.client
+                .put_bucket_versioning()
+                .bucket(&self.bucket)
+                .versioning_configuration(versioning.to_owned())
+                .send()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Returns true if the versioning of the bucket is enabled.
+    pub async fn bucket_versioning_is_enabled(&self) -> ZResult<bool> {
+        let response = self
+            .send("get_bucket_configuration", || {
+                self.client
+                    .get_bucket_versioning()
+                    .bucket(&self.bucket)
+                    .send()
+            })
+            .await?;
+        Ok(response.status() == Some(&BucketVersioningStatus::Enabled))
+    }
+
+    /// Sets the lifecycle configuration of the bucket, replacing the existing one if any.
+    pub async fn put_bucket_lifecycle(&self, rules: &[LifecycleRuleConfig]) -> ZResult<()> {
+        let lifecycle = BucketLifecycleConfiguration::builder()
+            .set_rules(Some(
+                rules
+                    .iter()
+                    .map(LifecycleRuleConfig::to_lifecycle_rule)
+                    .collect(),
+            ))
+            .build();
+        self.send("configure_bucket", || {
+            self.client
+                .put_bucket_lifecycle_configuration()
+                .bucket(&self.bucket)
+                .lifecycle_configuration(lifecycle.to_owned())
+                .send()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieves the lifecycle rules currently installed on the bucket.
+    pub async fn get_bucket_lifecycle(&self) -> ZResult<Vec<LifecycleRule>> {
+        let result = self
+            .send("get_bucket_configuration", || {
+                self.client
+                    .get_bucket_lifecycle_configuration()
+                    .bucket(&self.bucket)
+                    .send()
+            })
+            .await;
+        match result {
+            Ok(output) => Ok(output.rules().unwrap_or_default().to_vec()),
+            Err(err) if err.kind() == S3ErrorKind::ConfigurationMissing => Ok(vec![]),
+            Err(err) => Err(err
+                .context(format!(
+                    "Couldn't get lifecycle configuration of bucket '{self}'"
+                ))
+                .into()),
         }
     }
 
     /// Deletes the bucket associated to this storage.
     ///
-    /// In order to fulfill this operation, all the contained files in the bucket are deleted.
+    /// In order to fulfill this operation, the bucket is emptied first, see
+    /// [S3Client::empty_bucket].
     pub async fn delete_bucket(&self) -> ZResult<()> {
-        let objects = self.list_objects_in_bucket().await?;
-        self.delete_objects_in_bucket(objects).await?;
-        self.client
-            .delete_bucket()
-            .bucket(&self.bucket)
-            .send()
-            .await?;
+        self.empty_bucket().await?;
+        self.send("delete_bucket", || {
+            self.client.delete_bucket().bucket(&self.bucket).send()
+        })
+        .await?;
         log::debug!("Deleted bucket '{}'.", self.bucket.to_owned());
         Ok(())
     }
 
-    /// Lists all the objects contained in the bucket.
+    /// Empties the bucket: aborts its in-progress multipart uploads and deletes all its objects,
+    /// including their former versions and delete markers.
+    pub async fn empty_bucket(&self) -> ZResult<()> {
+        self.abort_multipart_uploads().await?;
+
+        // Objects may be written while the bucket is emptied, hence the bucket is listed again
+        // until both listings are empty.
+        loop {
+            let mut deleted = 0;
+            let mut key_marker = None;
+            let mut version_id_marker = None;
+            loop {
+                let response = self
+                    .send("list", || {
+                        self.client
+                            .list_object_versions()
+                            .bucket(&self.bucket)
+                            .set_key_marker(key_marker.to_owned())
+                            .set_version_id_marker(version_id_marker.to_owned())
+                            .send()
+                    })
+                    .await?;
+                let versions = response
+                    .versions()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|version| (version.key(), version.version_id()));
+                let delete_markers = response
+                    .delete_markers()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|delete_marker| (delete_marker.key(), delete_marker.version_id()));
+                let identifiers: Vec<ObjectIdentifier> = versions
+                    .chain(delete_markers)
+                    .map(|(key, version_id)| {
+                        ObjectIdentifier::builder()
+                            .set_key(key.map(|key| key.to_owned()))
+                            .set_version_id(version_id.map(|version_id| version_id.to_owned()))
+                            .build()
+                    })
+                    .collect();
+                deleted += identifiers.len();
+                self.delete_object_identifiers(identifiers).await?;
+                if !response.is_truncated() {
+                    break;
+                }
+                key_marker = response.next_key_marker().map(|marker| marker.to_owned());
+                version_id_marker = response
+                    .next_version_id_marker()
+                    .map(|marker| marker.to_owned());
+            }
+
+            // Some S3 implementations don't list the objects of unversioned buckets as versions.
+            let identifiers: Vec<ObjectIdentifier> = self
+                .list_all_objects()
+                .await?
+                .iter()
+                .map(|object| {
+                    ObjectIdentifier::builder()
+                        .set_key(object.key().map(|key| key.to_owned()))
+                        .build()
+                })
+                .collect();
+            deleted += identifiers.len();
+            self.delete_object_identifiers(identifiers).await?;
+            if deleted == 0 {
+                break;
+            }
+        }
+        log::debug!("Emptied bucket '{}'.", self.bucket);
+        Ok(())
+    }
+
+    /// Aborts all the multipart uploads in progress in the bucket.
+    async fn abort_multipart_uploads(&self) -> ZResult<()> {
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+        loop {
+            let response = self
+                .send("list", || {
+                    self.client
+                        .list_multipart_uploads()
+                        .bucket(&self.bucket)
+                        .set_key_marker(key_marker.to_owned())
+                        .set_upload_id_marker(upload_id_marker.to_owned())
+                        .send()
+                })
+                .await?;
+            for upload in response.uploads().unwrap_or_default() {
+                if let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) {
+                    self.send("delete", || {
+                        self.client
+                            .abort_multipart_upload()
+                            .bucket(&self.bucket)
+                            .key(key)
+                            .upload_id(upload_id)
+                            .send()
+                    })
+                    .await?;
+                    log::debug!("Aborted multipart upload of '{}' in '{}'.", key, self);
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            key_marker = response.next_key_marker().map(|marker| marker.to_owned());
+            upload_id_marker = response
+                .next_upload_id_marker()
+                .map(|marker| marker.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Deletes the objects (or object versions) specified, in batches of at most
+    /// [MAX_DELETE_OBJECTS_BATCH] objects. Fails if any of them couldn't be deleted.
+    async fn delete_object_identifiers(&self, identifiers: Vec<ObjectIdentifier>) -> ZResult<()> {
+        for batch in identifiers.chunks(MAX_DELETE_OBJECTS_BATCH) {
+            let delete = Delete::builder()
+                .set_objects(Some(batch.to_vec()))
+                .quiet(true)
+                .build();
+            let output = self
+                .send("delete", || {
+                    self.client
+                        .delete_objects()
+                        .bucket(&self.bucket)
+                        .delete(delete.to_owned())
+                        .send()
+                })
+                .await?;
+            let errors = output.errors().unwrap_or_default();
+            if let Some(error) = errors.first() {
+                return Err(zerror!(
+                    "Couldn't delete {} objects from '{}', e.g. '{}': {} ({})",
+                    errors.len(),
+                    self,
+                    error.key().unwrap_or_default(),
+                    error.message().unwrap_or_default(),
+                    error.code().unwrap_or_default()
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists all the objects contained in the bucket, except for the manifest written by the
+    /// `snapshot` closure strategy.
     pub async fn list_objects_in_bucket(&self) -> ZResult<Vec<Object>> {
-        let response = self
-            .client
-            .list_objects_v2()
-            .bucket(self.bucket.to_owned())
-            .send()
-            .await?;
-        Ok(response.contents().unwrap_or_default().to_vec())
+        let mut objects = self.list_all_objects().await?;
+        objects.retain(|object| object.key() != Some(MANIFEST_KEY));
+        self.metrics.record_listing(
+            objects.len(),
+            objects
+                .iter()
+                .map(|object| object.size().max(0) as u64)
+                .sum(),
+        );
+        Ok(objects)
+    }
+
+    /// Lists all the objects contained in the bucket.
+    async fn list_all_objects(&self) -> ZResult<Vec<Object>> {
+        let mut objects = vec![];
+        let mut continuation_token = None;
+        loop {
+            let response = self
+                .send("list", || {
+                    self.client
+                        .list_objects_v2()
+                        .bucket(self.bucket.to_owned())
+                        .set_continuation_token(continuation_token.to_owned())
+                        .send()
+                })
+                .await?;
+            objects.extend_from_slice(response.contents().unwrap_or_default());
+            if !response.is_truncated() {
+                break;
+            }
+            continuation_token = response
+                .next_continuation_token()
+                .map(|token| token.to_owned());
+        }
+        Ok(objects)
     }
 }
 
@@ -261,3 +1187,26 @@ impl std::fmt::Display for S3Client {
         write!(f, "{}", self.bucket)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_parts_cover_the_object() {
+        let size = 2 * COPY_PART_SIZE + 1;
+        assert_eq!(
+            copy_part_ranges(size),
+            vec![
+                (0, COPY_PART_SIZE - 1),
+                (COPY_PART_SIZE, 2 * COPY_PART_SIZE - 1),
+                (2 * COPY_PART_SIZE, 2 * COPY_PART_SIZE),
+            ]
+        );
+        // Objects of up to 5 TiB fit in the maximum amount of parts.
+        let size = 5 * 1024 * 1024 * 1024 * 1024;
+        let ranges = copy_part_ranges(size);
+        assert!(ranges.len() as i64 <= MAX_UPLOAD_PARTS);
+        assert_eq!(ranges.last().map(|(_, last)| *last), Some(size - 1));
+    }
+}
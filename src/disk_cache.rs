@@ -0,0 +1,704 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aws_sdk_s3::model::StorageClass;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+use zenoh::prelude::Encoding;
+use zenoh::time::Timestamp;
+use zenoh::Result as ZResult;
+use zenoh_buffers::buffer::SplitBuffer;
+use zenoh_core::zerror;
+
+use crate::client::{PutObjectOptions, S3Client};
+use crate::error::{S3Error, S3ErrorKind};
+use crate::properties::DiskCacheProperties;
+use crate::retention::ObjectLock;
+use crate::TIMESTAMP_METADATA_KEY;
+
+// Disk cache properties
+pub const DISK_CACHE_PROP: &str = "disk_cache";
+const DISK_CACHE_PATH: &str = "path";
+
+const ENTRY_FILE_EXTENSION: &str = "sample";
+
+/// Configuration of the local disk cache of a storage, declared under the `disk_cache` field of
+/// the storage configuration:
+///
+/// ```
+/// disk_cache: {
+///   // Directory holding the cached samples, created if missing.
+///   path: "/var/lib/zenoh/s3-cache",
+///   // Maximum size of the cached samples, in bytes (1 GiB by default).
+///   max_size: 1073741824,
+///   // Period, in seconds, at which the samples written while the bucket was unreachable are
+///   // written into the bucket (10 by default).
+///   reconcile_interval: 10,
+/// }
+/// ```
+#[derive(Clone)]
+pub(crate) struct DiskCacheConfig {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub reconcile_interval: Duration,
+}
+
+impl DiskCacheConfig {
+    /// Creates a new instance of [DiskCacheConfig] from the `disk_cache` field of the storage
+    /// configuration.
+    pub fn new(properties: DiskCacheProperties) -> ZResult<Self> {
+        if properties.path.is_empty() {
+            return Err(zerror!(
+                "Property `{DISK_CACHE_PROP}.{DISK_CACHE_PATH}` must be a non-empty path"
+            )
+            .into());
+        }
+        Ok(DiskCacheConfig {
+            path: PathBuf::from(properties.path),
+            max_size: properties.max_size,
+            reconcile_interval: Duration::from_secs(properties.reconcile_interval.get()),
+        })
+    }
+}
+
+/// Header of a cached sample, written as the first line of its file and followed by its payload.
+/// Besides the sample, it holds what is needed to write it into the bucket.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct DiskEntry {
+    /// The stripped key of the sample.
+    pub key: String,
+    pub object_key: String,
+    pub timestamp: String,
+    pub encoding: String,
+    /// Whether the entry stands for a deletion.
+    pub deleted: bool,
+    /// Whether the entry hasn't been written into the bucket yet.
+    pub dirty: bool,
+    pub metadata: HashMap<String, String>,
+    pub storage_class: Option<String>,
+    pub tagging: Option<String>,
+    /// ETag of the object the sample was read from or written to, if known.
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// Object Lock settings of the object to write, if any.
+    #[serde(default)]
+    pub object_lock: Option<ObjectLock>,
+}
+
+impl DiskEntry {
+    pub fn timestamp(&self) -> ZResult<Timestamp> {
+        Timestamp::from_str(&self.timestamp).map_err(|e| {
+            zerror!(
+                "Invalid timestamp in disk cache for '{}': {:?}",
+                self.key,
+                e
+            )
+            .into()
+        })
+    }
+}
+
+struct IndexEntry {
+    /// File holding the entry, named after the hash of its key (see [DiskCache::allocate_file]).
+    file: PathBuf,
+    timestamp: String,
+    etag: Option<String>,
+    size: u64,
+    dirty: bool,
+    /// Position of the entry in the LRU order.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct DiskCacheState {
+    entries: HashMap<String, IndexEntry>,
+    /// Keys of the entries, from the least recently used to the most recently used.
+    lru: BTreeMap<u64, String>,
+    /// Keys owning the files of the entries, including the files being written.
+    files: HashMap<PathBuf, String>,
+    tick: u64,
+    size: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    /// Amount of dirty entries which couldn't be written into the bucket for another reason than
+    /// the bucket being unreachable.
+    reconcile_failures: u64,
+}
+
+impl DiskCacheState {
+    fn insert(&mut self, file: PathBuf, entry: &DiskEntry, size: u64) {
+        self.remove(&entry.key);
+        self.tick += 1;
+        self.lru.insert(self.tick, entry.key.to_owned());
+        self.files.insert(file.to_owned(), entry.key.to_owned());
+        self.size += size;
+        self.entries.insert(
+            entry.key.to_owned(),
+            IndexEntry {
+                file,
+                timestamp: entry.timestamp.to_owned(),
+                etag: entry.etag.to_owned(),
+                size,
+                dirty: entry.dirty,
+                tick: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) -> Option<IndexEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.tick);
+        if self
+            .files
+            .get(&entry.file)
+            .map_or(false, |owner| owner == key)
+        {
+            self.files.remove(&entry.file);
+        }
+        self.size -= entry.size;
+        Some(entry)
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(key) {
+            let previous_tick = std::mem::replace(&mut entry.tick, tick);
+            self.lru.remove(&previous_tick);
+            self.lru.insert(tick, key.to_owned());
+        }
+    }
+}
+
+/// Cache of the samples of a storage in a local directory, serving the GET operations while the
+/// bucket is unreachable and keeping the samples written meanwhile until they can be written into
+/// the bucket. The cache is reloaded from the directory when the storage is created again.
+pub(crate) struct DiskCache {
+    config: DiskCacheConfig,
+    state: Mutex<DiskCacheState>,
+    // Runtime of the storage, running the blocking writes of the entries.
+    runtime: Handle,
+}
+
+impl DiskCache {
+    /// Opens the disk cache, indexing the samples already held by its directory from the least
+    /// recently written to the most recently written.
+    pub fn open(config: DiskCacheConfig, runtime: Handle) -> ZResult<Self> {
+        fs::create_dir_all(&config.path).map_err(|e| {
+            zerror!(
+                "Unable to create disk cache directory {}: {}",
+                config.path.display(),
+                e
+            )
+        })?;
+        let mut files = vec![];
+        for dir_entry in fs::read_dir(&config.path)? {
+            let path = dir_entry?.path();
+            if path
+                .extension()
+                .map_or(true, |ext| ext != ENTRY_FILE_EXTENSION)
+            {
+                continue;
+            }
+            let modified = fs::metadata(&path)?.modified()?;
+            files.push((modified, path));
+        }
+        files.sort();
+
+        let mut state = DiskCacheState::default();
+        for (_, path) in files {
+            match read_header(&path).and_then(|entry| check_file_name(&path, &entry).map(|_| entry))
+            {
+                Ok(entry) => {
+                    let size = fs::metadata(&path)?.len();
+                    // Only the most recently written file of a key is kept.
+                    if let Some(previous) = state.remove(&entry.key) {
+                        let _ = fs::remove_file(&previous.file);
+                    }
+                    state.insert(path, &entry, size);
+                }
+                Err(e) => {
+                    log::warn!("Removing invalid disk cache file {}: {}", path.display(), e);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        log::debug!(
+            "Disk cache {} opened with {} samples.",
+            config.path.display(),
+            state.entries.len()
+        );
+        Ok(DiskCache {
+            config,
+            state: Mutex::new(state),
+            runtime,
+        })
+    }
+
+    /// Retrieves the cached entry of the key and its value (None for deletions), if any.
+    pub fn get(&self, key: &str) -> ZResult<Option<(DiskEntry, Option<zenoh::value::Value>)>> {
+        let file = {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.get(key).map(|entry| entry.file.to_owned()) {
+                Some(file) => {
+                    state.hits += 1;
+                    state.touch(key);
+                    file
+                }
+                None => {
+                    state.misses += 1;
+                    return Ok(None);
+                }
+            }
+        };
+        let mut reader = BufReader::new(fs::File::open(&file)?);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let entry: DiskEntry = serde_json::from_str(&header)?;
+        if entry.key != key {
+            return Ok(None);
+        }
+        if entry.deleted {
+            return Ok(Some((entry, None)));
+        }
+        let mut payload = vec![];
+        reader.read_to_end(&mut payload)?;
+        let value = zenoh::value::Value::from(payload);
+        let value = match Encoding::try_from(entry.encoding.to_owned()) {
+            Ok(encoding) => value.encoding(encoding),
+            Err(_) => value,
+        };
+        Ok(Some((entry, Some(value))))
+    }
+
+    /// Returns the cached sample of the key, if any (None for deletions), as long as the entry
+    /// hasn't been written into the bucket yet.
+    pub fn get_dirty(
+        &self,
+        key: &str,
+    ) -> ZResult<Option<(DiskEntry, Option<zenoh::value::Value>)>> {
+        let dirty = self
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .get(key)
+            .map_or(false, |entry| entry.dirty);
+        if dirty {
+            self.get(key)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Writes the entry and the payload of the sample into the cache, evicting the least recently
+    /// used entries which are not dirty if the cache exceeds its maximum size. The files are
+    /// written on the blocking threads of the runtime of the storage.
+    pub async fn store(
+        self: &Arc<Self>,
+        entry: DiskEntry,
+        value: Option<zenoh::value::Value>,
+    ) -> ZResult<()> {
+        let disk_cache = self.clone();
+        self.runtime
+            .spawn_blocking(move || disk_cache.store_blocking(&entry, value.as_ref()))
+            .await
+            .map_err(|e| zerror!("Disk cache write task failed: {e}"))?
+    }
+
+    fn store_blocking(
+        &self,
+        entry: &DiskEntry,
+        value: Option<&zenoh::value::Value>,
+    ) -> ZResult<()> {
+        let (file, tmp_file) = self.allocate_file(&entry.key);
+        let size = match write_entry(&tmp_file, &file, entry, value) {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp_file);
+                let mut state = self.state.lock().unwrap();
+                let indexed = state
+                    .entries
+                    .get(&entry.key)
+                    .map_or(false, |indexed| indexed.file == file);
+                if !indexed {
+                    state.files.remove(&file);
+                }
+                return Err(e);
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(previous) = state.remove(&entry.key) {
+            if previous.file != file {
+                let _ = fs::remove_file(&previous.file);
+            }
+        }
+        state.insert(file, entry, size);
+        while state.size > self.config.max_size {
+            let lru_key = state
+                .lru
+                .values()
+                .find(|key| state.entries.get(*key).map_or(false, |entry| !entry.dirty))
+                .cloned();
+            let lru_key = match lru_key {
+                Some(lru_key) if lru_key != entry.key => lru_key,
+                _ => break,
+            };
+            if let Some(evicted) = state.remove(&lru_key) {
+                let _ = fs::remove_file(&evicted.file);
+                state.evictions += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reserves the file of the entry of the key, along with a temporary file to write it before
+    /// renaming it. Files are named after the hash of their key, suffixed with a counter when the
+    /// file is owned by another key whose hash collides.
+    fn allocate_file(&self, key: &str) -> (PathBuf, PathBuf) {
+        let hash = fnv1a(key);
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let file = (0..)
+            .map(|n| self.config.path.join(entry_file_name(hash, n)))
+            .find(|file| state.files.get(file).map_or(true, |owner| owner == key))
+            .unwrap();
+        state.files.insert(file.to_owned(), key.to_owned());
+        let tmp_file = file.with_extension(format!("{tick}.tmp"));
+        (file, tmp_file)
+    }
+
+    /// Returns true if the cache holds the sample of the key with the given timestamp.
+    pub fn contains(&self, key: &str, timestamp: &Timestamp) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .get(key)
+            .map_or(false, |entry| entry.timestamp == timestamp.to_string())
+    }
+
+    /// Returns the ETag of the object the sample cached for the key was read from or written to,
+    /// if known and if the entry has been written into the bucket.
+    pub fn clean_etag(&self, key: &str) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .get(key)
+            .filter(|entry| !entry.dirty)
+            .and_then(|entry| entry.etag.to_owned())
+    }
+
+    /// Removes the entry of the key from the cache.
+    pub fn remove(&self, key: &str) {
+        if let Some(entry) = self.state.lock().unwrap().remove(key) {
+            let _ = fs::remove_file(&entry.file);
+        }
+    }
+
+    /// Keys of the entries which haven't been written into the bucket yet.
+    fn dirty_keys(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(key, _)| key.to_owned())
+            .collect()
+    }
+
+    /// Statistics of the cache, reported in the admin status of the storage.
+    pub fn status(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "path": self.config.path.display().to_string(),
+            "entries": state.entries.len(),
+            "dirty": state.entries.values().filter(|entry| entry.dirty).count(),
+            "size": state.size,
+            "max_size": self.config.max_size,
+            "hits": state.hits,
+            "misses": state.misses,
+            "evictions": state.evictions,
+            "reconcile_failures": state.reconcile_failures,
+        })
+    }
+}
+
+/// Writes the entry into the temporary file, then renames it into the file of the entry. Returns
+/// the size of the file.
+fn write_entry(
+    tmp_file: &Path,
+    file: &Path,
+    entry: &DiskEntry,
+    value: Option<&zenoh::value::Value>,
+) -> ZResult<u64> {
+    {
+        let mut writer = fs::File::create(tmp_file)?;
+        serde_json::to_writer(&mut writer, entry)?;
+        writer.write_all(b"\n")?;
+        if let Some(value) = value {
+            writer.write_all(&value.payload.contiguous())?;
+        }
+        writer.sync_all()?;
+    }
+    fs::rename(tmp_file, file)?;
+    Ok(fs::metadata(file)?.len())
+}
+
+/// Name of the `n`-th candidate file of the keys whose hash is `hash`.
+fn entry_file_name(hash: u64, n: usize) -> String {
+    match n {
+        0 => format!("{hash:016x}.{ENTRY_FILE_EXTENSION}"),
+        n => format!("{hash:016x}-{n}.{ENTRY_FILE_EXTENSION}"),
+    }
+}
+
+/// Checks that the file is named after the hash of the key of its entry, as written by
+/// [DiskCache::store].
+fn check_file_name(path: &Path, entry: &DiskEntry) -> ZResult<()> {
+    let hash = format!("{:016x}", fnv1a(&entry.key));
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let matches = match stem.strip_prefix(&hash) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix('-')
+            .map_or(false, |n| n.parse::<usize>().is_ok()),
+        None => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(zerror!("the file doesn't match the key '{}'", entry.key).into())
+    }
+}
+
+fn read_header(path: &Path) -> ZResult<DiskEntry> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    Ok(serde_json::from_str(&header)?)
+}
+
+// Stable hash of the keys, naming the files of the entries.
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Periodically writes into the bucket the entries of the disk cache written while the bucket
+/// was unreachable, until the task is aborted.
+pub(crate) async fn run_reconciler(client: Arc<S3Client>, disk_cache: Arc<DiskCache>) {
+    let mut interval = tokio::time::interval(disk_cache.config.reconcile_interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = reconcile(&client, &disk_cache).await {
+            log::debug!("Unable to reconcile disk cache of '{}': {}", client, e);
+        }
+    }
+}
+
+/// Writes the dirty entries into the bucket, unless the bucket holds a more recent sample for
+/// their key. Stops at the first retryable error, the bucket being likely unreachable, or if the
+/// bucket is missing, while the entries failing for another reason are skipped until the next
+/// reconciliation.
+async fn reconcile(client: &S3Client, disk_cache: &Arc<DiskCache>) -> ZResult<()> {
+    for key in disk_cache.dirty_keys() {
+        if let Err(e) = reconcile_entry(client, disk_cache, &key).await {
+            if S3Error::is_retryable_error(&e)
+                || S3Error::kind_of(&e) == Some(S3ErrorKind::BucketMissing)
+            {
+                return Err(e);
+            }
+            disk_cache.state.lock().unwrap().reconcile_failures += 1;
+            log::warn!(
+                "Unable to write disk cache entry of '{}' into '{}', skipping it: {}",
+                key,
+                client,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn reconcile_entry(client: &S3Client, disk_cache: &Arc<DiskCache>, key: &str) -> ZResult<()> {
+    let (mut entry, value) = match disk_cache.get_dirty(key)? {
+        Some(dirty) => dirty,
+        None => return Ok(()),
+    };
+    let timestamp = entry.timestamp()?;
+    let stored_timestamp = match client.get_head_object(&entry.object_key).await {
+        Ok(head) => head
+            .metadata()
+            .and_then(|metadata| metadata.get(TIMESTAMP_METADATA_KEY))
+            .and_then(|timestamp| Timestamp::from_str(timestamp).ok()),
+        // A missing bucket is reported as such by the client, not as an absent object.
+        Err(e) if S3Error::kind_of(&e) == Some(S3ErrorKind::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    if stored_timestamp.map_or(false, |stored_timestamp| stored_timestamp >= timestamp) {
+        log::debug!(
+            "Dropping disk cache entry of '{}', a more recent sample is stored.",
+            key
+        );
+        disk_cache.remove(key);
+        return Ok(());
+    }
+    match value {
+        Some(value) => {
+            let options = PutObjectOptions {
+                storage_class: entry.storage_class.as_deref().map(StorageClass::from),
+                tagging: entry.tagging.to_owned(),
+                object_lock: entry.object_lock.to_owned(),
+            };
+            let output = client
+                .put_object(
+                    entry.object_key.to_owned(),
+                    value.clone(),
+                    Some(entry.metadata.to_owned()),
+                    options,
+                )
+                .await?;
+            entry.dirty = false;
+            entry.etag = output.e_tag().map(|etag| etag.to_owned());
+            disk_cache.store(entry, Some(value)).await?;
+        }
+        None => {
+            client.delete_object(entry.object_key.to_owned()).await?;
+            disk_cache.remove(key);
+        }
+    }
+    log::debug!(
+        "Reconciled disk cache entry of '{}' with '{}'.",
+        key,
+        client
+    );
+    Ok(())
+}
+
+/// Builds a cache entry that is not dirty out of a sample retrieved from the bucket.
+pub(crate) fn clean_entry(
+    key: &str,
+    object_key: &str,
+    timestamp: &Timestamp,
+    value: &zenoh::value::Value,
+    etag: Option<String>,
+) -> DiskEntry {
+    DiskEntry {
+        key: key.to_owned(),
+        object_key: object_key.to_owned(),
+        timestamp: timestamp.to_string(),
+        encoding: value.encoding.to_string(),
+        deleted: false,
+        dirty: false,
+        metadata: HashMap::new(),
+        storage_class: None,
+        tagging: None,
+        etag,
+        object_lock: None,
+    }
+}
+
+impl Drop for DiskCache {
+    fn drop(&mut self) {
+        let dirty = self.dirty_keys().len();
+        if dirty > 0 {
+            log::warn!(
+                "Closing disk cache {} with {} samples not written into the bucket yet, they will \
+                    be written when the storage is created again.",
+                self.config.path.display(),
+                dirty
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::properties::{DEFAULT_DISK_CACHE_MAX_SIZE, DEFAULT_RECONCILE_INTERVAL_SECS};
+
+    fn entry(key: &str) -> DiskEntry {
+        clean_entry(
+            key,
+            key,
+            &uhlc::HLC::default().new_timestamp(),
+            &zenoh::value::Value::from(vec![0u8; 8]),
+            None,
+        )
+    }
+
+    #[test]
+    fn colliding_keys_get_distinct_files() {
+        let path = std::env::temp_dir().join(format!("zenoh-s3-disk-cache-{}", std::process::id()));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = DiskCacheConfig {
+            path: path.to_owned(),
+            max_size: DEFAULT_DISK_CACHE_MAX_SIZE,
+            reconcile_interval: Duration::from_secs(DEFAULT_RECONCILE_INTERVAL_SECS),
+        };
+        let disk_cache =
+            Arc::new(DiskCache::open(config.to_owned(), runtime.handle().to_owned()).unwrap());
+
+        // Another key owns the file named after the hash of "a".
+        let file = path.join(entry_file_name(fnv1a("a"), 0));
+        disk_cache
+            .state
+            .lock()
+            .unwrap()
+            .files
+            .insert(file.to_owned(), "b".to_string());
+        let (allocated, _) = disk_cache.allocate_file("a");
+        assert_eq!(allocated, path.join(entry_file_name(fnv1a("a"), 1)));
+        disk_cache.state.lock().unwrap().files.clear();
+
+        runtime
+            .block_on(disk_cache.store(entry("a"), Some(zenoh::value::Value::from(vec![1u8]))))
+            .unwrap();
+        runtime
+            .block_on(disk_cache.store(entry("c"), Some(zenoh::value::Value::from(vec![2u8]))))
+            .unwrap();
+        drop(disk_cache);
+
+        // The entries are found again, the key of each file being checked.
+        let disk_cache = DiskCache::open(config, runtime.handle().to_owned()).unwrap();
+        let (entry_a, value_a) = disk_cache.get("a").unwrap().unwrap();
+        assert_eq!(entry_a.key, "a");
+        assert_eq!(value_a.unwrap().payload.contiguous().as_ref(), &[1u8]);
+        assert!(disk_cache.get("c").unwrap().is_some());
+        assert!(check_file_name(&file, &entry("a")).is_ok());
+        assert!(check_file_name(&path.join(entry_file_name(fnv1a("a"), 2)), &entry("a")).is_ok());
+        assert!(check_file_name(&file, &entry("c")).is_err());
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,329 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::fmt;
+
+use aws_sdk_s3::types::SdkError;
+use aws_smithy_types::retry::ProvideErrorKind;
+use zenoh_core::zerror;
+
+/// Class of the failures of the S3 requests and of the operations of the storages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum S3ErrorKind {
+    /// The object, or object version, doesn't exist.
+    NotFound,
+    /// The object still matches the ETag of a conditional request.
+    NotModified,
+    /// The credentials are invalid or not allowed to perform the request.
+    AccessDenied,
+    /// The request was rejected because of the request rate.
+    Throttled,
+    /// The bucket doesn't exist.
+    BucketMissing,
+    /// The bucket to create already exists and is owned by the credentials used.
+    BucketAlreadyOwned,
+    /// The bucket has no configuration of the requested kind, e.g. no lifecycle rules.
+    ConfigurationMissing,
+    /// The object is archived in a storage class from which it must be restored to be retrieved.
+    Archived,
+    /// The object lacks valid zenoh metadata, e.g. its timestamp.
+    InvalidMetadata,
+    /// The request timed out.
+    Timeout,
+    /// The request couldn't be sent, e.g. the endpoint is unreachable.
+    Network,
+    /// The server failed to process the request.
+    ServerError,
+    /// The TLS configuration is invalid.
+    Tls,
+    /// The configuration of the volume or of the storage is invalid.
+    Config,
+    /// Any other failure.
+    Other,
+}
+
+impl S3ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            S3ErrorKind::NotFound => "not_found",
+            S3ErrorKind::NotModified => "not_modified",
+            S3ErrorKind::AccessDenied => "access_denied",
+            S3ErrorKind::Throttled => "throttled",
+            S3ErrorKind::BucketMissing => "bucket_missing",
+            S3ErrorKind::BucketAlreadyOwned => "bucket_already_owned",
+            S3ErrorKind::ConfigurationMissing => "configuration_missing",
+            S3ErrorKind::Archived => "archived",
+            S3ErrorKind::InvalidMetadata => "invalid_metadata",
+            S3ErrorKind::Timeout => "timeout",
+            S3ErrorKind::Network => "network",
+            S3ErrorKind::ServerError => "server_error",
+            S3ErrorKind::Tls => "tls",
+            S3ErrorKind::Config => "config",
+            S3ErrorKind::Other => "other",
+        }
+    }
+
+    /// Classifies the error of a response from its S3 error code, if any, or its HTTP status.
+    fn classify(code: Option<&str>, status: u16) -> Self {
+        match code {
+            Some("NoSuchBucket") => S3ErrorKind::BucketMissing,
+            Some("NoSuchKey" | "NoSuchVersion" | "NotFound") => S3ErrorKind::NotFound,
+            Some(
+                "AccessDenied"
+                | "AllAccessDisabled"
+                | "InvalidAccessKeyId"
+                | "SignatureDoesNotMatch",
+            ) => S3ErrorKind::AccessDenied,
+            Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestLimitExceeded") => {
+                S3ErrorKind::Throttled
+            }
+            Some("RequestTimeout") => S3ErrorKind::Timeout,
+            Some("InvalidObjectState") => S3ErrorKind::Archived,
+            Some("BucketAlreadyOwnedByYou") => S3ErrorKind::BucketAlreadyOwned,
+            Some("NoSuchLifecycleConfiguration") => S3ErrorKind::ConfigurationMissing,
+            _ => match status {
+                304 => S3ErrorKind::NotModified,
+                403 => S3ErrorKind::AccessDenied,
+                404 => S3ErrorKind::NotFound,
+                429 => S3ErrorKind::Throttled,
+                500..=599 => S3ErrorKind::ServerError,
+                _ => S3ErrorKind::Other,
+            },
+        }
+    }
+}
+
+impl fmt::Display for S3ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error of the S3 backend, carrying its [S3ErrorKind] and, for the errors returned by S3, the
+/// S3 error code and the ID of the failed request.
+#[derive(Debug)]
+pub struct S3Error {
+    kind: S3ErrorKind,
+    code: Option<String>,
+    request_id: Option<String>,
+    message: String,
+}
+
+impl S3Error {
+    pub fn new(kind: S3ErrorKind, message: impl fmt::Display) -> Self {
+        S3Error {
+            kind,
+            code: None,
+            request_id: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn kind(&self) -> S3ErrorKind {
+        self.kind
+    }
+
+    /// The S3 error code (e.g. `NoSuchKey`), or the HTTP status of the response if it has none,
+    /// if the error was returned by S3.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The ID assigned by the server to the failed request, to be correlated with its logs.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Returns true if the request may succeed if retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            S3ErrorKind::Throttled
+                | S3ErrorKind::Timeout
+                | S3ErrorKind::Network
+                | S3ErrorKind::ServerError
+        )
+    }
+
+    /// Prefixes the message of the error with the context specified.
+    pub fn context(mut self, context: impl fmt::Display) -> Self {
+        self.message = format!("{context}: {}", self.message);
+        self
+    }
+
+    /// Kind of the error if it's an [S3Error].
+    pub fn kind_of(err: &zenoh_core::Error) -> Option<S3ErrorKind> {
+        err.downcast_ref::<S3Error>().map(S3Error::kind)
+    }
+
+    /// Returns true if the error is an [S3Error] which may not happen if the request is retried.
+    pub fn is_retryable_error(err: &zenoh_core::Error) -> bool {
+        err.downcast_ref::<S3Error>()
+            .map_or(false, S3Error::is_retryable)
+    }
+}
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request ID: {request_id})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for S3Error {}
+
+impl<E> From<SdkError<E>> for S3Error
+where
+    E: ProvideErrorKind + std::error::Error + 'static,
+{
+    fn from(err: SdkError<E>) -> Self {
+        let message = err.to_string();
+        match err {
+            SdkError::ServiceError { err, raw } => {
+                let status = raw.http().status().as_u16();
+                let code = err.code().map(|code| code.to_owned());
+                let request_id = raw
+                    .http()
+                    .headers()
+                    .get("x-amz-request-id")
+                    .and_then(|request_id| request_id.to_str().ok())
+                    .map(|request_id| request_id.to_owned());
+                S3Error {
+                    kind: S3ErrorKind::classify(code.as_deref(), status),
+                    code: code.or_else(|| Some(status.to_string())),
+                    request_id,
+                    message,
+                }
+            }
+            SdkError::TimeoutError { .. } => S3Error::new(S3ErrorKind::Timeout, message),
+            SdkError::DispatchFailure { .. } => S3Error::new(S3ErrorKind::Network, message),
+            _ => S3Error::new(S3ErrorKind::Other, message),
+        }
+    }
+}
+
+/// Prefixes the message of the error with the context specified, keeping its kind if it's an
+/// [S3Error].
+pub(crate) fn with_context(
+    err: zenoh_core::Error,
+    context: impl fmt::Display,
+) -> zenoh_core::Error {
+    match err.downcast::<S3Error>() {
+        Ok(err) => Box::new(err.context(context)),
+        Err(err) => zerror!("{context}: {err}").into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::error::{GetObjectError, HeadObjectError};
+    use aws_smithy_http::body::SdkBody;
+    use aws_smithy_http::operation;
+
+    fn raw_response(status: u16) -> operation::Response {
+        operation::Response::new(
+            http::Response::builder()
+                .status(status)
+                .header("x-amz-request-id", "4442587FB7D0A2F9")
+                .body(SdkBody::empty())
+                .unwrap(),
+        )
+    }
+
+    fn service_error(code: &str, status: u16) -> S3Error {
+        let err = aws_smithy_types::Error::builder()
+            .code(code)
+            .message("error message")
+            .build();
+        S3Error::from(SdkError::ServiceError {
+            err: GetObjectError::generic(err),
+            raw: raw_response(status),
+        })
+    }
+
+    #[test]
+    fn errors_are_classified_by_code_then_status() {
+        let classify = S3ErrorKind::classify;
+        assert_eq!(classify(Some("NoSuchKey"), 404), S3ErrorKind::NotFound);
+        assert_eq!(
+            classify(Some("NoSuchBucket"), 404),
+            S3ErrorKind::BucketMissing
+        );
+        assert_eq!(
+            classify(Some("NoSuchLifecycleConfiguration"), 404),
+            S3ErrorKind::ConfigurationMissing
+        );
+        // The responses to HEAD requests have no body, hence no error code.
+        assert_eq!(classify(None, 404), S3ErrorKind::NotFound);
+        assert_eq!(
+            classify(Some("AccessDenied"), 403),
+            S3ErrorKind::AccessDenied
+        );
+        assert_eq!(classify(None, 403), S3ErrorKind::AccessDenied);
+        assert_eq!(classify(Some("SlowDown"), 503), S3ErrorKind::Throttled);
+        assert_eq!(classify(None, 429), S3ErrorKind::Throttled);
+        assert_eq!(
+            classify(Some("InternalError"), 500),
+            S3ErrorKind::ServerError
+        );
+        assert_eq!(classify(None, 503), S3ErrorKind::ServerError);
+        assert_eq!(classify(Some("InvalidArgument"), 400), S3ErrorKind::Other);
+    }
+
+    #[test]
+    fn service_errors_carry_their_code_and_request_id() {
+        let err = service_error("NoSuchBucket", 404);
+        assert_eq!(err.kind(), S3ErrorKind::BucketMissing);
+        assert_eq!(err.code(), Some("NoSuchBucket"));
+        assert_eq!(err.request_id(), Some("4442587FB7D0A2F9"));
+        assert!(err.to_string().ends_with(" (request ID: 4442587FB7D0A2F9)"));
+        assert!(!err.is_retryable());
+        assert!(service_error("SlowDown", 503).is_retryable());
+
+        // Bodiless HEAD 404 responses are reported with their HTTP status as code.
+        let err = S3Error::from(SdkError::ServiceError {
+            err: HeadObjectError::generic(aws_smithy_types::Error::builder().build()),
+            raw: raw_response(404),
+        });
+        assert_eq!(err.kind(), S3ErrorKind::NotFound);
+        assert_eq!(err.code(), Some("404"));
+    }
+
+    #[test]
+    fn transport_errors_are_retryable() {
+        let err = S3Error::from(SdkError::<GetObjectError>::TimeoutError("timed out".into()));
+        assert_eq!(err.kind(), S3ErrorKind::Timeout);
+        assert_eq!(err.code(), None);
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn context_keeps_the_kind_of_s3_errors() {
+        let err: zenoh_core::Error = Box::new(service_error("NoSuchKey", 404));
+        let err = with_context(err, "Get operation failed");
+        assert_eq!(S3Error::kind_of(&err), Some(S3ErrorKind::NotFound));
+        assert!(err.to_string().starts_with("Get operation failed: "));
+
+        let err = with_context(zerror!("invalid key").into(), "Put operation failed");
+        assert_eq!(S3Error::kind_of(&err), None);
+        assert!(!S3Error::is_retryable_error(&err));
+        assert!(err
+            .to_string()
+            .contains("Put operation failed: invalid key"));
+    }
+}
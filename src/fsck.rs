@@ -0,0 +1,360 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use uhlc::ID;
+use zenoh::prelude::r#async::*;
+use zenoh::prelude::{Encoding, OwnedKeyExpr};
+use zenoh::queryable::Query;
+use zenoh::time::Timestamp;
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+use crate::client::{BucketLocation, S3Client};
+use crate::session::StorageSession;
+use crate::utils::timestamp_from_date_time;
+use crate::{NONE_KEY, TIMESTAMP_METADATA_KEY};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssueKind {
+    /// The object name isn't a valid key expression.
+    InvalidKey,
+    /// The head object couldn't be retrieved.
+    Unreadable,
+    /// The object has no `timestamp_uhlc` metadata.
+    MissingTimestamp,
+    /// The `timestamp_uhlc` metadata of the object isn't a valid timestamp.
+    InvalidTimestamp,
+    /// The object has no content encoding.
+    MissingEncoding,
+    /// The content encoding of the object isn't a valid zenoh encoding.
+    InvalidEncoding,
+}
+
+/// Inconsistency found on an object of the bucket.
+#[derive(Clone, Debug, Serialize)]
+pub struct FsckIssue {
+    pub key: String,
+    pub kind: FsckIssueKind,
+    pub detail: String,
+    /// Whether the issue has been repaired.
+    pub repaired: bool,
+}
+
+/// Outcome of a consistency check of a bucket.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FsckReport {
+    /// Amount of objects scanned.
+    pub scanned: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+/// Checks that all the objects of the bucket of a storage can be served through zenoh, that is
+/// that their name is a valid key expression and they hold a timestamp and an encoding.
+///
+/// If a zenoh ID is provided, the objects lacking a valid timestamp are repaired by stamping them
+/// with a timestamp made of their `LastModified` date and that ID. With `adopt_foreign_objects`,
+/// the objects lacking a content encoding are not reported, their content type being served as
+/// encoding by the storages adopting foreign objects.
+pub async fn check_bucket(
+    location: &BucketLocation,
+    repair_id: Option<ID>,
+    adopt_foreign_objects: bool,
+) -> ZResult<FsckReport> {
+    let client = location.client().await?;
+    fsck(&client, repair_id, adopt_foreign_objects).await
+}
+
+pub(crate) async fn fsck(
+    client: &S3Client,
+    repair_id: Option<ID>,
+    adopt_foreign_objects: bool,
+) -> ZResult<FsckReport> {
+    let mut report = FsckReport::default();
+    for object in client.list_objects_in_bucket().await? {
+        let object_key = match object.key() {
+            Some(key) => key,
+            None => continue,
+        };
+        report.scanned += 1;
+        let mut issue = |kind: FsckIssueKind, detail: String, repaired: bool| {
+            report.issues.push(FsckIssue {
+                key: object_key.to_owned(),
+                kind,
+                detail,
+                repaired,
+            })
+        };
+
+        let key = object_key.trim_start_matches('/');
+        if key != NONE_KEY {
+            if let Err(e) = OwnedKeyExpr::try_from(key) {
+                issue(FsckIssueKind::InvalidKey, e.to_string(), false);
+            }
+        }
+
+        let head = match client.get_head_object(object_key).await {
+            Ok(head) => head,
+            Err(e) => {
+                issue(FsckIssueKind::Unreadable, e.to_string(), false);
+                continue;
+            }
+        };
+
+        match head.content_encoding() {
+            None if adopt_foreign_objects => (),
+            None => issue(
+                FsckIssueKind::MissingEncoding,
+                "No content encoding".to_string(),
+                false,
+            ),
+            Some(encoding) => {
+                if let Err(e) = Encoding::try_from(encoding.to_owned()) {
+                    issue(FsckIssueKind::InvalidEncoding, e.to_string(), false);
+                }
+            }
+        }
+
+        let timestamp = head
+            .metadata()
+            .and_then(|metadata| metadata.get(TIMESTAMP_METADATA_KEY));
+        let timestamp_issue = match timestamp {
+            None => Some((
+                FsckIssueKind::MissingTimestamp,
+                format!("No `{TIMESTAMP_METADATA_KEY}` metadata"),
+            )),
+            Some(timestamp) => Timestamp::from_str(timestamp)
+                .err()
+                .map(|e| (FsckIssueKind::InvalidTimestamp, format!("{e:?}"))),
+        };
+        if let Some((kind, detail)) = timestamp_issue {
+            let repaired = match (repair_id, head.last_modified()) {
+                (Some(id), Some(last_modified)) => {
+                    let timestamp = timestamp_from_date_time(last_modified, id);
+                    let mut metadata = head.metadata().cloned().unwrap_or_default();
+                    metadata.insert(TIMESTAMP_METADATA_KEY.to_string(), timestamp.to_string());
+                    match client
+                        .replace_object_metadata(object_key, &head, metadata)
+                        .await
+                    {
+                        Ok(()) => true,
+                        Err(e) => {
+                            log::warn!("Unable to repair timestamp of '{}': {}", object_key, e);
+                            false
+                        }
+                    }
+                }
+                _ => false,
+            };
+            issue(kind, detail, repaired);
+        }
+    }
+    log::debug!(
+        "Checked {} objects of '{}', found {} issues.",
+        report.scanned,
+        client,
+        report.issues.len()
+    );
+    Ok(report)
+}
+
+/// Marks a consistency check as running in the admin status of the storage, returning false if
+/// one is already running.
+pub(crate) fn begin_fsck(fsck_report: &Mutex<Option<serde_json::Value>>) -> bool {
+    let mut fsck_report = fsck_report.lock().unwrap();
+    if fsck_report.as_ref() == Some(&serde_json::json!("running")) {
+        return false;
+    }
+    *fsck_report = Some(serde_json::json!("running"));
+    true
+}
+
+/// Runs a consistency check of the bucket, storing its report, or the error it failed with, as
+/// the `fsck_report` of the admin status of the storage. The check must have been marked as
+/// running with [begin_fsck].
+pub(crate) async fn run_fsck(
+    client: &S3Client,
+    repair_id: Option<ID>,
+    adopt_foreign_objects: bool,
+    fsck_report: &Mutex<Option<serde_json::Value>>,
+) -> serde_json::Value {
+    let report = match fsck(client, repair_id, adopt_foreign_objects).await {
+        Ok(report) => serde_json::to_value(report)
+            .unwrap_or_else(|e| serde_json::json!({ "error": e.to_string() })),
+        Err(e) => {
+            log::warn!("Consistency check of '{}' failed: {}", client, e);
+            serde_json::json!({ "error": e.to_string() })
+        }
+    };
+    *fsck_report.lock().unwrap() = Some(report.to_owned());
+    report
+}
+
+/// Key expression, under the admin space of the storage manager, of the queryable running the
+/// consistency check of a storage.
+pub(crate) fn fsck_admin_key_expr(storage_name: &str) -> String {
+    format!("@/router/*/status/plugins/storage_manager/storages/{storage_name}/fsck")
+}
+
+/// Request made by a query on the admin key of the storage ([fsck_admin_key_expr]).
+#[derive(Debug, PartialEq, Eq)]
+enum FsckRequest {
+    /// Reply with the report of the last check.
+    Report,
+    /// Run a new check, only reporting the issues found.
+    Run,
+    /// Run a new check, repairing the objects lacking a valid timestamp.
+    Repair,
+}
+
+impl FsckRequest {
+    /// Parses the `run=true` and `repair=true` parameters of a query, the other parameters being
+    /// ignored.
+    fn from_parameters(parameters: &str) -> ZResult<Self> {
+        let mut request = FsckRequest::Report;
+        for parameter in parameters
+            .split('&')
+            .filter(|parameter| !parameter.is_empty())
+        {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, "true"));
+            let requested = match name {
+                "run" => FsckRequest::Run,
+                "repair" => FsckRequest::Repair,
+                _ => continue,
+            };
+            match value {
+                "true" => {
+                    if request != FsckRequest::Repair {
+                        request = requested;
+                    }
+                }
+                "false" => (),
+                _ => {
+                    return Err(zerror!(
+                        "Invalid `{name}` parameter '{value}': expected true or false"
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Serves the queries on the admin key of the storage ([fsck_admin_key_expr]) with the session of
+/// the storage, until the task is aborted. Queries are replied with the report of the last check
+/// as JSON; a new check is only run on queries with the `run=true` parameter, or `repair=true` to
+/// also repair the objects lacking a valid timestamp if the storage has a `repair_id`. Queries
+/// requesting a check while one is running are rejected.
+pub(crate) async fn serve_fsck_queries(
+    client: Arc<S3Client>,
+    storage_name: String,
+    session: Arc<StorageSession>,
+    repair_id: Option<ID>,
+    adopt_foreign_objects: bool,
+    fsck_report: Arc<Mutex<Option<serde_json::Value>>>,
+) {
+    let key_expr = fsck_admin_key_expr(&storage_name);
+    let session = match session.get().await {
+        Ok(session) => session,
+        Err(e) => {
+            log::warn!("Unable to open session of '{}': {}", client, e);
+            return;
+        }
+    };
+    let queryable = match session.declare_queryable(&key_expr).res().await {
+        Ok(queryable) => queryable,
+        Err(e) => {
+            log::warn!("Unable to declare fsck queryable '{}': {}", key_expr, e);
+            return;
+        }
+    };
+    log::debug!(
+        "Serving consistency checks of '{}' on '{}'.",
+        client,
+        key_expr
+    );
+    while let Ok(query) = queryable.recv_async().await {
+        let repair_id = match FsckRequest::from_parameters(query.parameters()) {
+            Ok(FsckRequest::Report) => {
+                let report = fsck_report.lock().unwrap().to_owned();
+                reply(&query, Ok(report.unwrap_or_default())).await;
+                continue;
+            }
+            Ok(FsckRequest::Run) => None,
+            Ok(FsckRequest::Repair) if repair_id.is_some() => repair_id,
+            Ok(FsckRequest::Repair) => {
+                let e = format!("No `repair_zid` configured to repair '{client}'");
+                reply(&query, Err(e)).await;
+                continue;
+            }
+            Err(e) => {
+                reply(&query, Err(e.to_string())).await;
+                continue;
+            }
+        };
+        if !begin_fsck(&fsck_report) {
+            let e = format!("A consistency check of '{client}' is already running");
+            reply(&query, Err(e)).await;
+            continue;
+        }
+        // Run in its own task so that the queries received meanwhile are replied.
+        let client = client.clone();
+        let fsck_report = fsck_report.clone();
+        tokio::spawn(async move {
+            let report = run_fsck(&client, repair_id, adopt_foreign_objects, &fsck_report).await;
+            reply(&query, Ok(report)).await;
+        });
+    }
+}
+
+/// Replies to a query on the fsck admin key with a report as JSON, or with an error.
+async fn reply(query: &Query, report: Result<serde_json::Value, String>) {
+    let reply = report
+        .map(|report| {
+            let value = Value::from(report.to_string()).encoding(KnownEncoding::AppJson.into());
+            Sample::new(query.key_expr().to_owned(), value)
+        })
+        .map_err(Value::from);
+    if let Err(e) = query.reply(reply).res().await {
+        log::warn!(
+            "Unable to reply to fsck query on '{}': {}",
+            query.key_expr(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_are_only_run_on_request() {
+        let request = |parameters| FsckRequest::from_parameters(parameters).unwrap();
+        assert_eq!(request(""), FsckRequest::Report);
+        assert_eq!(request("_time=[now(-1h)..]"), FsckRequest::Report);
+        assert_eq!(request("run=false"), FsckRequest::Report);
+        assert_eq!(request("run=true"), FsckRequest::Run);
+        assert_eq!(request("run"), FsckRequest::Run);
+        assert_eq!(request("repair=true"), FsckRequest::Repair);
+        assert_eq!(request("repair=true&run=true"), FsckRequest::Repair);
+        assert!(FsckRequest::from_parameters("repair=yes").is_err());
+    }
+}
@@ -12,24 +12,58 @@
 //   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
 //
 
+pub mod bucket_creation;
+pub mod cache;
+pub mod change_detection;
 pub mod client;
 pub mod config;
+pub mod disk_cache;
+pub mod error;
+pub mod fsck;
+pub mod lifecycle;
+pub mod metrics;
+pub mod properties;
+pub mod rate_limit;
+pub mod retention;
+pub mod session;
+pub mod snapshot;
 pub mod utils;
 
 use async_std::sync::Arc;
 use async_trait::async_trait;
 
-use client::S3Client;
-use config::{S3Config, TlsClientConfig, TLS_PROP};
+use cache::{Cache, CacheLookup};
+use change_detection::PublishedObjects;
+use client::{PutObjectOptions, S3Client};
+use config::{
+    resolve_references, ForeignObjectsConfig, S3Config, TimestampSource, TlsClientConfig, TLS_PROP,
+};
+use disk_cache::{clean_entry, DiskCache, DiskEntry};
+use error::{with_context, S3Error, S3ErrorKind};
 use futures::future::join_all;
-use futures::stream::FuturesUnordered;
-use utils::S3Key;
+use futures::stream::{FuturesUnordered, StreamExt};
+use lifecycle::{lifecycle_drift, LifecycleRuleConfig};
+use rate_limit::RequestLimiter;
+use session::StorageSession;
+use utils::{
+    expiry_from, is_expired, is_tombstone, system_time_from_date_time, timestamp_from_date_time,
+    KeyExprRules, LatencyRecorder, S3Key,
+};
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::vec;
 
+use aws_sdk_s3::model::{DeleteMarkerEntry, ObjectVersion};
+use aws_sdk_s3::output::GetObjectOutput;
+use aws_sdk_s3::types::DateTime;
+use tracing::field::Empty;
+use tracing::Instrument;
+
 use zenoh::prelude::*;
 use zenoh::properties::Properties;
 use zenoh::time::Timestamp;
@@ -37,21 +71,51 @@ use zenoh::Result as ZResult;
 use zenoh_backend_traits::config::{StorageConfig, VolumeConfig};
 use zenoh_backend_traits::StorageInsertionResult;
 use zenoh_backend_traits::*;
+use zenoh_buffers::buffer::SplitBuffer;
 use zenoh_core::zerror;
+use zenoh_util::time_range::{TimeBound, TimeExpr, TimeRange};
 
 // Properties used by the Backend
 pub const PROP_S3_ENDPOINT: &str = "url";
 pub const PROP_S3_REGION: &str = "region";
+pub const PROP_S3_WORKER_THREADS: &str = "worker_threads";
+pub const PROP_S3_METRICS_ENDPOINT: &str = "metrics_endpoint";
+pub const PROP_S3_VERSIONING: &str = "versioning";
 
 // Special key for None (when the prefix being stripped exactly matches the key)
 pub const NONE_KEY: &str = "@@none_key@@";
 
+// Key of the manifest of the samples written by the `snapshot` closure strategy.
+pub const MANIFEST_KEY: &str = "@@manifest@@";
+
 // Metadata keys
 pub const TIMESTAMP_METADATA_KEY: &str = "timestamp_uhlc";
+pub const EXPIRES_AT_METADATA_KEY: &str = "expires_at";
+pub const TOMBSTONE_METADATA_KEY: &str = "tombstone";
+
+// Identifier of the lifecycle rule installed on the bucket when `ttl_lifecycle_rule` is enabled.
+const TTL_LIFECYCLE_RULE_ID: &str = "zenoh-ttl";
+
+// Selector parameter used for history queries.
+const TIME_PARAMETER: &str = "_time";
+
+// Maximum difference between the timestamp of a sample and the `LastModified` date of its object
+// version: S3 rejects the requests signed more than 15 minutes away from its clock.
+const MAX_TIMESTAMP_SKEW: Duration = Duration::from_secs(15 * 60);
 
-// Amount of worker threads to be used by the tokio runtime of the [S3Storage] to handle incoming
-// operations.
-const STORAGE_WORKER_THREADS: usize = 2;
+// Maximum amount of objects that can be deleted with a single DeleteObjects request.
+pub(crate) const MAX_DELETE_OBJECTS_BATCH: usize = 1000;
+
+// Maximum time the removal of a storage waits for its `on_closure` operation, which then goes on
+// in the background until it completes or reaches `on_closure_timeout`.
+const ON_CLOSURE_WAIT: Duration = Duration::from_secs(10);
+
+// Maximum amount of HEAD requests issued at the same time by the TTL sweeper.
+const MAX_CONCURRENT_SWEEP_REQUESTS: usize = 16;
+
+// Default amount of worker threads of the tokio runtime shared by the storages of the volume to
+// handle incoming operations.
+const DEFAULT_WORKER_THREADS: usize = 4;
 
 const GIT_VERSION: &str = git_version::git_version!(prefix = "v", cargo_prefix = "v");
 lazy_static::lazy_static! {
@@ -74,33 +138,127 @@ pub fn create_volume(mut config: VolumeConfig) -> ZResult<Box<dyn Volume>> {
 
     let endpoint = get_optional_string_property(PROP_S3_ENDPOINT, &config)?;
     let region = get_optional_string_property(PROP_S3_REGION, &config)?;
+    let worker_threads = get_worker_threads(&config)?;
+    let versioning = get_versioning(&config)?;
+    let metrics_endpoint = get_optional_string_property(PROP_S3_METRICS_ENDPOINT, &config)?
+        .map(|addr| parse_metrics_endpoint(&addr))
+        .transpose()?;
 
     let mut properties = Properties::default();
     properties.insert("version".into(), LONG_VERSION.clone());
+    properties.insert(PROP_S3_WORKER_THREADS.into(), worker_threads.to_string());
 
-    let admin_status = HashMap::from(properties)
+    let mut admin_status: serde_json::Value = HashMap::from(properties)
         .into_iter()
         .map(|(k, v)| (k, serde_json::Value::String(v)))
         .collect();
 
-    let tls_config = load_tls_config(&config)?;
+    let tls_config = load_tls_config(&config).map_err(|e| S3Error::new(S3ErrorKind::Tls, e))?;
+
+    if let serde_json::Value::Object(admin_status) = &mut admin_status {
+        admin_status.insert("endpoint".into(), endpoint.to_owned().into());
+        admin_status.insert("region".into(), region.to_owned().into());
+        admin_status.insert(
+            TLS_PROP.into(),
+            tls_config
+                .as_ref()
+                .map_or_else(|| serde_json::Value::Null, TlsClientConfig::status),
+        );
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .thread_name("zenoh-s3-backend")
+        .enable_all()
+        .build()
+        .map_err(|e| zerror!("Couldn't create tokio runtime: {e}"))?;
+    log::debug!("Tokio runtime created for storage operations ({worker_threads} worker threads).");
+
+    if let Some(addr) = metrics_endpoint {
+        #[cfg(feature = "stats")]
+        runtime.spawn(metrics::registry::serve(addr));
+        #[cfg(not(feature = "stats"))]
+        log::warn!(
+            "Property '{PROP_S3_METRICS_ENDPOINT}' ({addr}) is ignored: the S3 backend was built \
+                without the `stats` feature."
+        );
+    }
 
     Ok(Box::new(S3Backend {
         admin_status,
         endpoint,
         region,
         tls_config,
+        runtime: Arc::new(runtime),
+        versioning,
+        closure_failures: Arc::new(Mutex::new(serde_json::Map::new())),
     }))
 }
 
 fn get_optional_string_property(property: &str, config: &VolumeConfig) -> ZResult<Option<String>> {
     match config.rest.get(property) {
-        Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+        Some(serde_json::Value::String(value)) => {
+            resolve_references(value).map(Some).map_err(|e| {
+                S3Error::new(S3ErrorKind::Config, format!("Property '{property}': {e}")).into()
+            })
+        }
         None => {
             log::debug!("Property '{property}' was not specified. ");
             Ok(None)
         }
-        _ => Err(zerror!("Property '{property}' for S3 Backend must be a string.").into()),
+        _ => Err(S3Error::new(
+            S3ErrorKind::Config,
+            format!("Property '{property}' for S3 Backend must be a string."),
+        )
+        .into()),
+    }
+}
+
+/// Parses the `metrics_endpoint` volume property: a socket address, or a port alone which is bound
+/// on the loopback interface, the metrics revealing the buckets and the names of the storages.
+fn parse_metrics_endpoint(addr: &str) -> ZResult<std::net::SocketAddr> {
+    if let Ok(port) = addr.parse::<u16>() {
+        return Ok(std::net::SocketAddr::from((
+            std::net::Ipv4Addr::LOCALHOST,
+            port,
+        )));
+    }
+    std::net::SocketAddr::from_str(addr).map_err(|e| {
+        S3Error::new(
+            S3ErrorKind::Config,
+            format!(
+                "Property '{PROP_S3_METRICS_ENDPOINT}' must be a port or a socket address: {e}"
+            ),
+        )
+        .into()
+    })
+}
+
+fn get_worker_threads(config: &VolumeConfig) -> ZResult<usize> {
+    match config.rest.get(PROP_S3_WORKER_THREADS) {
+        None => Ok(DEFAULT_WORKER_THREADS),
+        Some(serde_json::Value::Number(n)) if n.as_u64().map_or(false, |n| n > 0) => {
+            Ok(n.as_u64().unwrap() as usize)
+        }
+        _ => Err(S3Error::new(
+            S3ErrorKind::Config,
+            format!(
+                "Property '{PROP_S3_WORKER_THREADS}' for S3 Backend must be a positive number."
+            ),
+        )
+        .into()),
+    }
+}
+
+fn get_versioning(config: &VolumeConfig) -> ZResult<bool> {
+    match config.rest.get(PROP_S3_VERSIONING) {
+        None => Ok(false),
+        Some(serde_json::Value::Bool(versioning)) => Ok(*versioning),
+        _ => Err(S3Error::new(
+            S3ErrorKind::Config,
+            format!("Property '{PROP_S3_VERSIONING}' for S3 Backend must be a boolean."),
+        )
+        .into()),
     }
 }
 
@@ -117,18 +275,54 @@ pub struct S3Backend {
     endpoint: Option<String>,
     region: Option<String>,
     tls_config: Option<TlsClientConfig>,
+    // Runtime shared by all the storages of the volume.
+    runtime: Arc<tokio::runtime::Runtime>,
+    // Whether the volume holds versioned storages, which keep the history of the samples.
+    versioning: bool,
+    // Failures of the `on_closure` operations of the storages, per bucket.
+    closure_failures: Arc<Mutex<serde_json::Map<String, serde_json::Value>>>,
 }
 
 #[async_trait]
 impl Volume for S3Backend {
     fn get_admin_status(&self) -> serde_json::Value {
-        self.admin_status.clone()
+        let mut admin_status = self.admin_status.clone();
+        let closure_failures = self.closure_failures.lock().unwrap();
+        if let serde_json::Value::Object(admin_status) = &mut admin_status {
+            if !closure_failures.is_empty() {
+                admin_status.insert(
+                    "closure_failures".into(),
+                    serde_json::Value::Object(closure_failures.to_owned()),
+                );
+            }
+        }
+        admin_status
     }
 
     async fn create_storage(&mut self, config: StorageConfig) -> ZResult<Box<dyn Storage>> {
         log::debug!("Creating storage...");
-        let config: S3Config = S3Config::new(&config).await?;
+        let config: S3Config = S3Config::new(&config)
+            .await
+            .map_err(|e| S3Error::new(S3ErrorKind::Config, e))?;
 
+        if config.versioning && !self.versioning {
+            return Err(S3Error::new(
+                S3ErrorKind::Config,
+                format!(
+                    "Storage '{}' uses `versioning`, which requires its volume to declare \
+                        `{PROP_S3_VERSIONING}: true`",
+                    config.name
+                ),
+            )
+            .into());
+        }
+
+        // All the requests of the storage, including the ones of its background tasks, are issued
+        // within its limits.
+        let limiter = Arc::new(RequestLimiter::new(
+            config.max_concurrent_requests,
+            config.rate_limit.as_ref(),
+        ));
         let client = S3Client::new(
             config.credentials.to_owned(),
             config.bucket.to_owned(),
@@ -136,28 +330,211 @@ impl Volume for S3Backend {
             self.endpoint.to_owned(),
             self.tls_config.to_owned(),
         )
-        .await;
+        .await
+        .with_limiter(limiter.clone())
+        .with_storage_name(config.name.to_owned());
 
-        client
-            .create_bucket(config.reuse_bucket_is_enabled)
+        let client = Arc::new(client);
+        let storage_runtime = self.runtime.clone();
+
+        let client2 = client.clone();
+        let reuse_bucket = config.reuse_bucket_is_enabled;
+        let bucket_creation = config.bucket_creation.to_owned();
+        let bucket_created = storage_runtime
+            .spawn(async move { client2.create_bucket(reuse_bucket, &bucket_creation).await })
+            .await
             .map_err(|e| zerror!("Couldn't create storage: {e}"))?
+            .map_err(|e| with_context(e, "Couldn't create storage"))?
             .map_or_else(
-                || log::debug!("Reusing existing bucket '{}'.", client),
-                |_| log::debug!("Bucket '{}' successfully created.", client),
+                || {
+                    log::debug!("Reusing existing bucket '{}'.", client);
+                    false
+                },
+                |_| {
+                    log::debug!("Bucket '{}' successfully created.", client);
+                    true
+                },
             );
 
-        let storage_runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(STORAGE_WORKER_THREADS)
-            .enable_all()
-            .build()
-            .map_err(|e| zerror!(e))?;
+        if bucket_created {
+            let client2 = client.clone();
+            let bucket_creation = config.bucket_creation.to_owned();
+            let configured = storage_runtime
+                .spawn(async move { client2.configure_bucket(&bucket_creation).await })
+                .await
+                .map_err(|e| zerror!("Couldn't configure bucket: {e}"))?;
+            if let Err(e) = configured {
+                // A bucket missing part of its settings (e.g. a public access block) must not be
+                // left behind, nor be reused by the next attempt to create the storage.
+                let client2 = client.clone();
+                let deleted: ZResult<()> = storage_runtime
+                    .spawn(async move { client2.delete_bucket().await })
+                    .await
+                    .map_err(|e| zerror!("{e}").into())
+                    .and_then(|deleted| deleted);
+                match deleted {
+                    Ok(()) => log::debug!("Deleted misconfigured bucket '{}'.", client),
+                    Err(err) => log::error!(
+                        "Couldn't delete misconfigured bucket '{}', delete it manually: {}",
+                        client,
+                        err
+                    ),
+                }
+                return Err(with_context(e, "Couldn't configure bucket"));
+            }
+        }
+
+        let client2 = client.clone();
+        let bucket_region = match storage_runtime
+            .spawn(async move { client2.get_bucket_region().await })
+            .await
+            .map_err(|e| zerror!("Couldn't get bucket region: {e}"))?
+        {
+            Ok(region) => region.unwrap_or_else(|| "us-east-1".to_owned()).into(),
+            Err(e) => {
+                log::warn!("Couldn't get region of bucket '{}': {}", client, e);
+                serde_json::Value::Null
+            }
+        };
+        let bucket_status = serde_json::json!({
+            "name": config.bucket,
+            "region": bucket_region,
+            "created": bucket_created,
+            "endpoint": client.endpoint(),
+            "signing_region": client.signing_region(),
+        });
+
+        if config.versioning {
+            let client2 = client.clone();
+            storage_runtime
+                .spawn(async move { setup_bucket_versioning(&client2, bucket_created).await })
+                .await
+                .map_err(|e| zerror!("Couldn't set up bucket versioning: {e}"))?
+                .map_err(|e| with_context(e, "Couldn't set up bucket versioning"))?;
+        }
+
+        let mut lifecycle_rules = config.lifecycle.rules.to_owned();
+        if config.ttl_lifecycle_rule {
+            match ttl_lifecycle_rule(&config.ttl) {
+                Some(rule) => lifecycle_rules.push(rule),
+                None => log::warn!(
+                    "No `ttl` applying to all the keys of '{}', no TTL lifecycle rule installed.",
+                    client
+                ),
+            }
+        }
+        let lifecycle_status = if lifecycle_rules.is_empty() {
+            None
+        } else {
+            let client2 = client.clone();
+            let reconcile = config.lifecycle.reconcile;
+            let status = storage_runtime
+                .spawn(async move {
+                    setup_bucket_lifecycle(&client2, lifecycle_rules, bucket_created, reconcile)
+                        .await
+                })
+                .await
+                .map_err(|e| zerror!("Couldn't set up bucket lifecycle: {e}"))?
+                .map_err(|e| with_context(e, "Couldn't set up bucket lifecycle"))?;
+            Some(status)
+        };
+
+        // Opened on first use by the features requiring it.
+        let session = Arc::new(StorageSession::new(config.connect.to_owned()));
+
+        let fsck_report = Arc::new(Mutex::new(None));
+        let fsck_server = config.fsck.as_ref().and_then(|fsck_config| {
+            let repair_id = if config.is_read_only {
+                None
+            } else {
+                fsck_config.repair_id
+            };
+            let adopt_foreign_objects = config.adopt_foreign_objects.is_some();
+            if fsck_config.on_start {
+                let client2 = client.clone();
+                let fsck_report2 = fsck_report.clone();
+                fsck::begin_fsck(&fsck_report);
+                storage_runtime.spawn(async move {
+                    fsck::run_fsck(&client2, repair_id, adopt_foreign_objects, &fsck_report2).await
+                });
+            }
+            fsck_config.admin_queryable.then(|| {
+                storage_runtime.spawn(fsck::serve_fsck_queries(
+                    client.clone(),
+                    config.name.to_owned(),
+                    session.clone(),
+                    repair_id,
+                    adopt_foreign_objects,
+                    fsck_report.clone(),
+                ))
+            })
+        });
+
+        let ttl_sweeper = if !config.ttl.is_empty() && !config.is_read_only {
+            let client2 = client.clone();
+            let ttl = config.ttl.to_owned();
+            let period = config.ttl_sweep_interval;
+            Some(storage_runtime.spawn(async move { run_ttl_sweeper(client2, ttl, period).await }))
+        } else {
+            None
+        };
+
+        let published_objects = config
+            .change_detection
+            .as_ref()
+            .map(|_| Arc::new(PublishedObjects::default()));
+        let change_detector = config
+            .change_detection
+            .to_owned()
+            .zip(published_objects.clone())
+            .map(|(change_detection, published_objects2)| {
+                let client2 = client.clone();
+                let path_prefix = config.path_prefix.to_owned();
+                let session2 = session.clone();
+                storage_runtime.spawn(async move {
+                    change_detection::run_change_detection(
+                        client2,
+                        path_prefix,
+                        change_detection,
+                        session2,
+                        published_objects2,
+                    )
+                    .await
+                })
+            });
 
-        log::debug!("Tokio runtime created for storage operations.");
+        let cache = config.cache.to_owned().map(Cache::new);
+
+        let disk_cache = match config.disk_cache.to_owned() {
+            Some(disk_cache_config) => Some(Arc::new(
+                DiskCache::open(disk_cache_config, storage_runtime.handle().to_owned())
+                    .map_err(|e| zerror!("Couldn't open disk cache: {e}"))?,
+            )),
+            None => None,
+        };
+        let disk_reconciler = disk_cache.as_ref().map(|disk_cache| {
+            let client2 = client.clone();
+            let disk_cache2 = disk_cache.clone();
+            storage_runtime
+                .spawn(async move { disk_cache::run_reconciler(client2, disk_cache2).await })
+        });
 
         Ok(Box::new(S3Storage {
             config,
-            client: Arc::new(client),
+            client,
             runtime: storage_runtime,
+            limiter,
+            closure_failures: self.closure_failures.clone(),
+            ttl_sweeper,
+            change_detector,
+            published_objects,
+            cache,
+            disk_cache,
+            disk_reconciler,
+            lifecycle_status,
+            fsck_report,
+            fsck_server,
+            bucket_status,
         }))
     }
 
@@ -169,11 +546,16 @@ impl Volume for S3Backend {
         None
     }
 
-    /// Returns the capability of this backend
+    /// Returns the capability of this backend: the whole history of the samples is kept if the
+    /// volume holds versioned storages.
     fn get_capability(&self) -> Capability {
         Capability {
             persistence: Persistence::Durable,
-            history: History::Latest,
+            history: if self.versioning {
+                History::All
+            } else {
+                History::Latest
+            },
             read_cost: 1,
         }
     }
@@ -182,29 +564,122 @@ impl Volume for S3Backend {
 struct S3Storage {
     config: S3Config,
     client: Arc<S3Client>,
-    runtime: tokio::runtime::Runtime,
+    runtime: Arc<tokio::runtime::Runtime>,
+    // Bounds the amount and the rate of the requests issued by the storage.
+    limiter: Arc<RequestLimiter>,
+    closure_failures: Arc<Mutex<serde_json::Map<String, serde_json::Value>>>,
+    ttl_sweeper: Option<tokio::task::JoinHandle<()>>,
+    change_detector: Option<tokio::task::JoinHandle<()>>,
+    // Foreign objects published by the change detection, not to be written back.
+    published_objects: Option<Arc<PublishedObjects>>,
+    cache: Option<Cache>,
+    disk_cache: Option<Arc<DiskCache>>,
+    disk_reconciler: Option<tokio::task::JoinHandle<()>>,
+    lifecycle_status: Option<serde_json::Value>,
+    fsck_report: Arc<Mutex<Option<serde_json::Value>>>,
+    fsck_server: Option<tokio::task::JoinHandle<()>>,
+    // Name and region of the bucket, whether the storage created it and where its requests go.
+    bucket_status: serde_json::Value,
 }
 
 #[async_trait]
 impl Storage for S3Storage {
     fn get_admin_status(&self) -> serde_json::Value {
-        self.config.admin_status.to_owned()
+        let mut admin_status = self.config.admin_status.to_owned();
+        if let serde_json::Value::Object(admin_status) = &mut admin_status {
+            let mut bucket_status = self.bucket_status.to_owned();
+            if let (serde_json::Value::Object(bucket_status), serde_json::Value::Object(health)) =
+                (&mut bucket_status, self.client.metrics().health())
+            {
+                bucket_status.extend(health);
+            }
+            admin_status.insert("bucket_status".into(), bucket_status);
+            if let Some(lifecycle_status) = &self.lifecycle_status {
+                admin_status.insert("lifecycle_status".into(), lifecycle_status.to_owned());
+            }
+            if let Some(fsck_report) = self.fsck_report.lock().unwrap().as_ref() {
+                admin_status.insert("fsck_report".into(), fsck_report.to_owned());
+            }
+            if let Some(cache) = &self.cache {
+                admin_status.insert("cache_status".into(), cache.status());
+            }
+            if let Some(disk_cache) = &self.disk_cache {
+                admin_status.insert("disk_cache_status".into(), disk_cache.status());
+            }
+            admin_status.insert("requests_status".into(), self.limiter.status());
+            admin_status.insert("metrics".into(), self.client.metrics().status());
+            if !self.config.retention.is_empty() {
+                admin_status.insert(
+                    "retention_policy".into(),
+                    retention::retention_status(&self.config.retention),
+                );
+            }
+        }
+        admin_status
     }
 
     /// Function to retrieve the sample associated with a single key.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        err,
+        fields(
+            storage = %self.config.name,
+            bucket = %self.client,
+            key = Empty,
+            bytes = Empty,
+            latency_ms = Empty,
+        )
+    )]
     async fn get(
         &mut self,
         key: Option<OwnedKeyExpr>,
-        _parameters: &str,
+        parameters: &str,
     ) -> ZResult<Vec<StoredData>> {
+        let _latency = LatencyRecorder::start();
         let key = key.map_or_else(|| OwnedKeyExpr::from_str(NONE_KEY), Ok)?;
+        tracing::Span::current().record("key", key.as_str());
         log::debug!("GET called on client {}. Key: '{}'", self.client, key);
 
         let prefix = self.config.path_prefix.to_owned();
         let s3_key = S3Key::from_key_expr(prefix, key.to_owned())?;
 
-        let get_result = self.get_stored_value(&s3_key.into()).await?;
+        if self.config.versioning {
+            if let Some(time_range) = get_time_range(parameters)? {
+                let client2 = self.client.clone();
+                let key2: String = s3_key.into();
+                let foreign = self.config.adopt_foreign_objects.clone();
+                return self
+                    .spawn_request(async move {
+                        get_stored_versions(&client2, &key2, time_range, foreign.as_ref()).await
+                    })
+                    .await;
+            }
+        }
+
+        let lookup = self
+            .cache
+            .as_ref()
+            .map_or(CacheLookup::Miss, |cache| cache.lookup(&key));
+        let get_result = match lookup {
+            CacheLookup::Hit(cached) => cached,
+            lookup => {
+                let stale = match lookup {
+                    CacheLookup::Stale(stored, etag) => Some((stored, etag)),
+                    _ => None,
+                };
+                let (get_result, etag) = self.get_sample(&key, &s3_key.into(), stale).await?;
+                if let Some(cache) = &self.cache {
+                    let expiry = get_result
+                        .as_ref()
+                        .and_then(|(timestamp, _)| self.sample_expiry(&key, timestamp));
+                    cache.insert(key, get_result.clone(), etag, expiry);
+                }
+                get_result
+            }
+        };
         if let Some((timestamp, value)) = get_result {
+            tracing::Span::current().record("bytes", value.payload.len());
             let stored_data = StoredData { value, timestamp };
             Ok(vec![stored_data])
         } else {
@@ -213,27 +688,111 @@ impl Storage for S3Storage {
     }
 
     /// Function called for each incoming data ([`Sample`]) to be stored in this storage.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        err,
+        fields(
+            storage = %self.config.name,
+            bucket = %self.client,
+            key = Empty,
+            bytes = value.payload.len(),
+            latency_ms = Empty,
+        )
+    )]
     async fn put(
         &mut self,
         key: Option<OwnedKeyExpr>,
         value: Value,
         timestamp: Timestamp,
     ) -> ZResult<StorageInsertionResult> {
+        let _latency = LatencyRecorder::start();
         let key = key.map_or_else(|| OwnedKeyExpr::from_str(NONE_KEY), Ok)?;
+        tracing::Span::current().record("key", key.as_str());
         log::debug!("Put called on client {}. Key: '{}'", self.client, key);
 
-        let s3_key = S3Key::from_key_expr(self.config.path_prefix.to_owned(), key)
+        if let Some(published_objects) = &self.published_objects {
+            if published_objects.take(key.as_str(), &value.payload.contiguous()) {
+                log::debug!(
+                    "Not writing back externally written object '{}' published by change detection.",
+                    key
+                );
+                return Ok(StorageInsertionResult::Inserted);
+            }
+        }
+
+        let ttl = self.config.ttl.lookup(&key).cloned();
+        let tagging = self
+            .config
+            .tags
+            .lookup_rule(&key)
+            .map(|(key_expr, tags)| tags.render(key_expr, &key, &value.encoding.to_string()));
+        let options = PutObjectOptions {
+            storage_class: self.config.storage_class.lookup(&key).cloned(),
+            tagging,
+            object_lock: self
+                .config
+                .retention
+                .lookup(&key)
+                .map(|policy| policy.object_lock()),
+        };
+        let s3_key = S3Key::from_key_expr(self.config.path_prefix.to_owned(), key.to_owned())
             .map_or_else(|err| Err(zerror!("Error getting s3 key: {}", err)), Ok)?;
         if !self.config.is_read_only {
             let client2 = self.client.clone();
-            let key2 = s3_key.into();
+            let key2: String = s3_key.into();
+            let cached = self.cache.as_ref().map(|_| (timestamp, value.clone()));
             let mut metadata: HashMap<String, String> = HashMap::new();
             metadata.insert(TIMESTAMP_METADATA_KEY.to_string(), timestamp.to_string());
-            self.runtime
-                .spawn(async move { client2.put_object(key2, value, Some(metadata)).await })
+            if let Some(ttl) = ttl {
+                metadata.insert(
+                    EXPIRES_AT_METADATA_KEY.to_string(),
+                    expiry_from(&timestamp, &ttl).to_string(),
+                );
+            }
+            let disk_entry = self.disk_cache.as_ref().map(|_| {
+                let entry = DiskEntry {
+                    key: key.to_string(),
+                    object_key: key2.to_owned(),
+                    timestamp: timestamp.to_string(),
+                    encoding: value.encoding.to_string(),
+                    deleted: false,
+                    dirty: false,
+                    metadata: metadata.to_owned(),
+                    storage_class: options
+                        .storage_class
+                        .as_ref()
+                        .map(|storage_class| storage_class.as_str().to_owned()),
+                    tagging: options.tagging.to_owned(),
+                    etag: None,
+                    object_lock: options.object_lock.to_owned(),
+                };
+                (entry, value.clone())
+            });
+            let put_result: ZResult<Option<String>> = self
+                .spawn_request(async move {
+                    client2
+                        .put_object(key2, value, Some(metadata), options)
+                        .await
+                })
                 .await
-                .map_err(|e| zerror!("Put operation failed: {e}"))?
-                .map_err(|e| zerror!("Put operation failed: {e}"))?;
+                .map(|output| output.e_tag().map(|etag| etag.to_owned()))
+                .map_err(|e| with_context(e, "Put operation failed"));
+            let etag = put_result.as_ref().ok().cloned().flatten();
+            match disk_entry {
+                Some((mut entry, value)) => {
+                    entry.etag = etag.to_owned();
+                    self.store_in_disk_cache(entry, Some(value), put_result.map(|_| ()))
+                        .await?
+                }
+                None => {
+                    put_result?;
+                }
+            }
+            if let Some(cache) = &self.cache {
+                let expiry = self.sample_expiry(&key, &timestamp);
+                cache.insert(key, cached, etag, expiry);
+            }
             Ok(StorageInsertionResult::Inserted)
         } else {
             log::warn!("Received PUT for read-only DB on {} - ignored", s3_key);
@@ -242,24 +801,76 @@ impl Storage for S3Storage {
     }
 
     /// Function called for each incoming delete request to this storage.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        err,
+        fields(
+            storage = %self.config.name,
+            bucket = %self.client,
+            key = Empty,
+            latency_ms = Empty,
+        )
+    )]
     async fn delete(
         &mut self,
         key: Option<OwnedKeyExpr>,
-        _timestamp: Timestamp,
+        timestamp: Timestamp,
     ) -> ZResult<StorageInsertionResult> {
+        let _latency = LatencyRecorder::start();
         let key = key.map_or_else(|| OwnedKeyExpr::from_str(NONE_KEY), Ok)?;
+        tracing::Span::current().record("key", key.as_str());
         log::debug!("Delete called on client {}. Key: '{}'", self.client, key);
 
-        let s3_key = S3Key::from_key_expr(self.config.path_prefix.to_owned(), key)?;
+        let s3_key = S3Key::from_key_expr(self.config.path_prefix.to_owned(), key.to_owned())?;
         if !self.config.is_read_only {
+            let key2: String = s3_key.into();
+            if self.config.retention.lookup(&key).is_some() {
+                let client2 = self.client.clone();
+                let key3 = key2.to_owned();
+                self.spawn_request(
+                    async move { retention::check_not_locked(&client2, &key3).await },
+                )
+                .await
+                .map_err(|e| with_context(e, "Delete operation failed"))?;
+            }
             let client2 = self.client.clone();
-            let key2 = s3_key.into();
+            let disk_entry = self.disk_cache.as_ref().map(|_| DiskEntry {
+                key: key.to_string(),
+                object_key: key2.to_owned(),
+                timestamp: timestamp.to_string(),
+                encoding: String::new(),
+                deleted: true,
+                dirty: false,
+                metadata: HashMap::new(),
+                storage_class: None,
+                tagging: None,
+                etag: None,
+                object_lock: None,
+            });
 
-            self.runtime
-                .spawn(async move { client2.delete_object(key2).await })
+            let versioning = self.config.versioning;
+            let delete_result: ZResult<()> = self
+                .spawn_request(async move {
+                    let result = if versioning {
+                        delete_versioned_object(&client2, key2.to_owned(), &timestamp).await
+                    } else {
+                        client2.delete_object(key2.to_owned()).await.map(|_| ())
+                    };
+                    match result {
+                        Err(e) => Err(retention::explain_delete_failure(&client2, &key2, e).await),
+                        Ok(()) => Ok(()),
+                    }
+                })
                 .await
-                .map_err(|e| zerror!("Delete operation failed: {e}"))?
-                .map_err(|e| zerror!("Delete operation failed: {e}"))?;
+                .map_err(|e| with_context(e, "Delete operation failed"));
+            match disk_entry {
+                Some(entry) => self.store_in_disk_cache(entry, None, delete_result).await?,
+                None => delete_result?,
+            }
+            if let Some(cache) = &self.cache {
+                cache.insert(key, None, None, None);
+            }
             Ok(StorageInsertionResult::Deleted)
         } else {
             log::warn!("Received DELETE for read-only DB on {} - ignored", s3_key);
@@ -267,18 +878,29 @@ impl Storage for S3Storage {
         }
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        err,
+        fields(
+            storage = %self.config.name,
+            bucket = %self.client,
+            latency_ms = Empty,
+        )
+    )]
     async fn get_all_entries(&self) -> ZResult<Vec<(Option<OwnedKeyExpr>, Timestamp)>> {
+        let _latency = LatencyRecorder::start();
         let client = self.client.clone();
         let objects = self
-            .runtime
-            .spawn(async move { client.list_objects_in_bucket().await })
+            .spawn_request(async move { client.list_objects_in_bucket().await })
             .await
-            .map_err(|e| zerror!("Get operation failed: {e}"))?
-            .map_err(|e| zerror!("Get operation failed: {e}"))?;
+            .map_err(|e| with_context(e, "Get operation failed"))?;
 
         let futures = objects.into_iter().map(|object| {
             let client = self.client.clone();
-            self.runtime.spawn(async move {
+            let foreign = self.config.adopt_foreign_objects.clone();
+            let object_key = object.key().unwrap_or_default().to_owned();
+            let request = self.spawn_request(async move {
                 let key = object
                     .key()
                     .ok_or_else(|| zerror!("Could not get key for object {:?}", object))?;
@@ -292,109 +914,888 @@ impl Storage for S3Storage {
                                 zerror!("Unable to recreate key expression for '{}': {}.", key, err)
                             })?)
                         };
-                        let metadata = value.metadata.ok_or_else(|| {
-                            zerror!("Unable to retrieve metadata for key '{}'.", key)
-                        })?;
-                        let timestamp = metadata.get(TIMESTAMP_METADATA_KEY).ok_or_else(|| {
-                            zerror!("Unable to retrieve timestamp for key '{}'.", key)
-                        })?;
-                        Ok((
-                            key_expr,
-                            Timestamp::from_str(timestamp.as_str()).map_err(|e| {
-                                zerror!("Unable to obtain timestamp for key: {}. {:?}", key, e)
-                            })?,
-                        ))
+                        if value.metadata().map_or(false, |metadata| {
+                            is_expired(metadata) || is_tombstone(metadata)
+                        }) {
+                            return Ok(None);
+                        }
+                        let timestamp = object_timestamp(
+                            key,
+                            value.metadata(),
+                            value.last_modified(),
+                            foreign.as_ref(),
+                        )?;
+                        Ok(Some((key_expr, timestamp)))
                     }
                     Err(err) => Err(zerror!(
                         "Unable to get '{}' object from storage: {}",
                         key.to_owned(),
                         err
-                    )),
+                    )
+                    .into()),
                 }
-            })
+            });
+            async move { (object_key, request.await) }
         });
         let futures_results = join_all(futures.collect::<FuturesUnordered<_>>()).await;
-        let entries: Vec<(Option<OwnedKeyExpr>, Timestamp)> =
-            futures_results.into_iter().flatten().flatten().collect();
+        let mut entries: Vec<(Option<OwnedKeyExpr>, Timestamp)> = Vec::new();
+        for (object_key, result) in futures_results {
+            match result {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => (),
+                // Reported rather than failing the whole alignment, see the `fsck` property.
+                Err(e) => log::warn!(
+                    "Skipping object '{}' of '{}' from the stored entries: {}",
+                    object_key,
+                    self.client,
+                    e
+                ),
+            }
+        }
         Ok(entries)
     }
 }
 
 impl S3Storage {
-    async fn get_stored_value(&self, key: &String) -> ZResult<Option<(Timestamp, Value)>> {
+    /// Retrieves the sample stored for the key (None if there is none) and the ETag of its object
+    /// from the bucket or, if the bucket is unreachable, from the disk cache.
+    ///
+    /// The body of the object is only downloaded if its ETag differs from the one of the `stale`
+    /// sample of the memory cache or of the sample held by the disk cache, if any.
+    async fn get_sample(
+        &self,
+        key: &keyexpr,
+        object_key: &String,
+        stale: Option<((Timestamp, Value), String)>,
+    ) -> ZResult<(Option<(Timestamp, Value)>, Option<String>)> {
+        let disk_etag = match &stale {
+            Some(_) => None,
+            None => self
+                .disk_cache
+                .as_ref()
+                .and_then(|disk_cache| disk_cache.clean_etag(key)),
+        };
+        let etag = stale
+            .as_ref()
+            .map(|(_, etag)| etag.to_owned())
+            .or_else(|| disk_etag.to_owned());
+        let fetched = match self.get_stored_value(object_key, etag).await {
+            Ok(Fetched::Modified(stored, etag)) => Ok((stored, etag)),
+            Ok(Fetched::NotModified) => {
+                if let Some((stored, etag)) = stale {
+                    log::debug!("Object for key '{}' not modified, revalidated.", key);
+                    return Ok((Some(stored), Some(etag)));
+                }
+                if let Some(disk_cache) = &self.disk_cache {
+                    if let Some((entry, value)) = disk_cache.get(key)? {
+                        if entry.etag.is_some() && entry.etag == disk_etag {
+                            log::debug!("Object for key '{}' not modified, read from disk.", key);
+                            let timestamp = entry.timestamp()?;
+                            return Ok((value.map(|value| (timestamp, value)), entry.etag));
+                        }
+                    }
+                }
+                // The cached body vanished meanwhile.
+                self.get_stored_value(object_key, None)
+                    .await
+                    .and_then(|fetched| fetched.modified(key))
+            }
+            Err(e) => Err(e),
+        };
+
+        let disk_cache = match &self.disk_cache {
+            Some(disk_cache) => disk_cache,
+            None => return fetched,
+        };
+        match fetched {
+            Ok((stored, etag)) => {
+                // The samples written while the bucket was unreachable prevail until reconciled.
+                if let Some((entry, value)) = disk_cache.get_dirty(key)? {
+                    let timestamp = entry.timestamp()?;
+                    if stored
+                        .as_ref()
+                        .map_or(true, |(stored, _)| *stored < timestamp)
+                    {
+                        return Ok((value.map(|value| (timestamp, value)), None));
+                    }
+                }
+                match &stored {
+                    Some((timestamp, value)) if !disk_cache.contains(key, timestamp) => {
+                        let entry = clean_entry(key, object_key, timestamp, value, etag.to_owned());
+                        if let Err(e) = disk_cache.store(entry, Some(value.clone())).await {
+                            log::warn!("Unable to write '{}' into the disk cache: {}", key, e);
+                        }
+                    }
+                    Some(_) => {}
+                    None => disk_cache.remove(key),
+                }
+                Ok((stored, etag))
+            }
+            Err(e) => match disk_cache.get(key)? {
+                Some((entry, value)) => {
+                    log::warn!(
+                        "Serving '{}' from the disk cache, '{}' is unreachable: {}",
+                        key,
+                        self.client,
+                        e
+                    );
+                    let timestamp = entry.timestamp()?;
+                    Ok((value.map(|value| (timestamp, value)), None))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Updates the disk cache with the outcome of a PUT or DELETE operation. If the operation
+    /// failed with a retryable error, the bucket being likely unreachable, the entry is kept as
+    /// dirty in the disk cache until it's written into the bucket. Other errors are returned as
+    /// is, the operation being bound to fail again.
+    async fn store_in_disk_cache(
+        &self,
+        mut entry: DiskEntry,
+        value: Option<Value>,
+        result: ZResult<()>,
+    ) -> ZResult<()> {
+        let disk_cache = match &self.disk_cache {
+            Some(disk_cache) => disk_cache,
+            None => return result,
+        };
+        if let Err(e) = &result {
+            if !S3Error::is_retryable_error(e) {
+                return result;
+            }
+        }
+        entry.dirty = result.is_err();
+        let key = entry.key.to_owned();
+        match disk_cache.store(entry, value).await {
+            Ok(()) => {
+                if let Err(e) = result {
+                    log::warn!(
+                        "Keeping '{}' in the disk cache until it can be written into '{}': {}",
+                        key,
+                        self.client,
+                        e
+                    );
+                }
+                Ok(())
+            }
+            Err(e) => {
+                log::warn!("Unable to write '{}' into the disk cache: {}", key, e);
+                result
+            }
+        }
+    }
+
+    /// Returns the time at which the sample stored on the key with the given timestamp expires
+    /// according to the `ttl` of the storage, if any.
+    fn sample_expiry(&self, key: &keyexpr, timestamp: &Timestamp) -> Option<SystemTime> {
+        self.config
+            .ttl
+            .lookup(key)
+            .map(|ttl| timestamp.get_time().to_system_time() + *ttl)
+    }
+
+    async fn get_stored_value(&self, key: &String, etag: Option<String>) -> ZResult<Fetched> {
         let client2 = self.client.clone();
         let key2 = key.to_owned();
+        let foreign = self.config.adopt_foreign_objects.clone();
+        self.spawn_request(async move {
+            fetch_stored_value(&client2, &key2, etag.as_deref(), foreign.as_ref()).await
+        })
+        .await
+        .map_err(|e| with_context(e, format!("Get operation failed for key '{key}'")))
+    }
 
-        let output_result = match self
-            .runtime
-            .spawn(async move { client2.get_object(key2.as_str()).await })
+    /// Runs the request on the runtime of the volume, within the span of the calling operation.
+    /// The S3 requests it issues are bounded by the limiter of the client of the storage.
+    async fn spawn_request<T, F>(&self, request: F) -> ZResult<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = ZResult<T>> + Send + 'static,
+    {
+        self.runtime
+            .spawn(request.instrument(tracing::Span::current()))
             .await
-            .map_err(|e| zerror!("Get operation failed for key '{key}': {e}"))?
-        {
-            Ok(result) => Ok(result),
-            Err(e) => {
-                if e.to_string().contains("NoSuchKey") {
-                    return Ok(None);
+            .map_err(|e| zerror!("Request task failed: {e}"))?
+    }
+}
+
+/// Retrieves the timestamp and the value stored for the key specified. Returns None if there is
+/// no object associated to the key or if it has expired. The objects lacking timestamp metadata
+/// are adopted according to the `foreign` policy, if any.
+async fn get_stored_value(
+    client: &S3Client,
+    key: &str,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Option<(Timestamp, Value)>> {
+    fetch_stored_value(client, key, None, foreign)
+        .await?
+        .modified(key)
+        .map(|(stored, _)| stored)
+}
+
+/// Outcome of a conditional retrieval of the sample stored for a key.
+enum Fetched {
+    /// The object still has the ETag specified.
+    NotModified,
+    /// The sample stored for the key (None if there is none) and the ETag of its object.
+    Modified(Option<(Timestamp, Value)>, Option<String>),
+}
+
+impl Fetched {
+    fn modified(self, key: &str) -> ZResult<(Option<(Timestamp, Value)>, Option<String>)> {
+        match self {
+            Fetched::Modified(stored, etag) => Ok((stored, etag)),
+            Fetched::NotModified => {
+                Err(zerror!("Unexpected not modified response for key '{key}'").into())
+            }
+        }
+    }
+}
+
+/// Retrieves the sample stored for the key specified, unless the ETag of its object still matches
+/// the `etag` specified.
+async fn fetch_stored_value(
+    client: &S3Client,
+    key: &str,
+    etag: Option<&str>,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Fetched> {
+    let result = match etag {
+        Some(etag) => client.get_object_if_none_match(key, etag).await,
+        None => client.get_object(key).await.map(Some),
+    };
+    let output_result = match result {
+        Ok(Some(result)) => Ok(result),
+        Ok(None) => return Ok(Fetched::NotModified),
+        Err(e) => match S3Error::kind_of(&e) {
+            Some(S3ErrorKind::NotFound) => return Ok(Fetched::Modified(None, None)),
+            Some(S3ErrorKind::Archived) => Err(S3Error::new(
+                S3ErrorKind::Archived,
+                format!(
+                    "Object for key '{key}' is archived in a storage class (e.g. GLACIER or \
+                        DEEP_ARCHIVE) from which it must be restored before being retrieved."
+                ),
+            )
+            .into()),
+            _ => Err(with_context(
+                e,
+                format!("Get operation failed for key '{key}'"),
+            )),
+        },
+    }?;
+
+    let etag = output_result.e_tag().map(|etag| etag.to_owned());
+    let stored = read_stored_value(key, output_result, foreign).await?;
+    Ok(Fetched::Modified(stored, etag))
+}
+
+/// Reads the timestamp and the value stored in an object retrieved from the bucket. Returns None
+/// if the object has expired.
+async fn read_stored_value(
+    key: &str,
+    output_result: GetObjectOutput,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Option<(Timestamp, Value)>> {
+    if output_result.metadata().map_or(false, is_expired) {
+        log::debug!("Object for key '{key}' has expired.");
+        return Ok(None);
+    }
+    if output_result.metadata().map_or(false, is_tombstone) {
+        return Ok(None);
+    }
+    let timestamp = object_timestamp(
+        key,
+        output_result.metadata(),
+        output_result.last_modified(),
+        foreign,
+    )?;
+
+    // Objects written by other tools than zenoh usually carry a content type only.
+    let encoding = output_result
+        .content_encoding()
+        .or_else(|| output_result.content_type())
+        .map(|x| x.to_string());
+    let bytes = output_result
+        .body
+        .collect()
+        .await
+        .map(|data| data.into_bytes())
+        .map_err(|e| zerror!("Get operation failed. Couldn't process retrieved contents: {e}"))?;
+
+    let value = match encoding {
+        Some(encoding) => Encoding::try_from(encoding).map_or_else(
+            |_| Value::from(Vec::from(bytes.to_owned())),
+            |result| Value::from(Vec::from(bytes.to_owned())).encoding(result),
+        ),
+        None => Value::from(Vec::from(bytes)),
+    };
+    Ok(Some((timestamp, value)))
+}
+
+/// Retrieves the timestamp of an object from its `timestamp_uhlc` metadata. If the object lacks
+/// it, the timestamp is derived according to the `foreign` policy, if any.
+fn object_timestamp(
+    key: &str,
+    metadata: Option<&HashMap<String, String>>,
+    last_modified: Option<&DateTime>,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Timestamp> {
+    if let Some(timestamp) = metadata.and_then(|metadata| metadata.get(TIMESTAMP_METADATA_KEY)) {
+        return Timestamp::from_str(timestamp).map_err(|e| {
+            S3Error::new(
+                S3ErrorKind::InvalidMetadata,
+                format!("Unable to obtain timestamp for key: {}. {:?}", key, e),
+            )
+            .into()
+        });
+    }
+    let foreign = foreign.ok_or_else(|| {
+        S3Error::new(
+            S3ErrorKind::InvalidMetadata,
+            format!("Unable to retrieve timestamp for key '{}'.", key),
+        )
+    })?;
+    match &foreign.timestamp_source {
+        TimestampSource::LastModified => last_modified
+            .map(|last_modified| timestamp_from_date_time(last_modified, foreign.id))
+            .ok_or_else(|| {
+                S3Error::new(
+                    S3ErrorKind::InvalidMetadata,
+                    format!("Unable to retrieve last modification date of '{}'.", key),
+                )
+                .into()
+            }),
+        TimestampSource::Metadata(metadata_key) => {
+            let time = metadata
+                .and_then(|metadata| metadata.get(metadata_key))
+                .ok_or_else(|| {
+                    S3Error::new(
+                        S3ErrorKind::InvalidMetadata,
+                        format!(
+                            "Unable to retrieve `{}` metadata of '{}'.",
+                            metadata_key, key
+                        ),
+                    )
+                })?;
+            parse_foreign_time(time, foreign.id).ok_or_else(|| {
+                S3Error::new(
+                    S3ErrorKind::InvalidMetadata,
+                    format!(
+                        "Invalid time '{}' in `{}` metadata of '{}'.",
+                        time, metadata_key, key
+                    ),
+                )
+                .into()
+            })
+        }
+    }
+}
+
+/// Parses a time written by another tool than zenoh, either a zenoh timestamp, an RFC 3339 date
+/// or a number of seconds since the UNIX epoch.
+fn parse_foreign_time(time: &str, id: uhlc::ID) -> Option<Timestamp> {
+    if let Ok(timestamp) = Timestamp::from_str(time) {
+        return Some(timestamp);
+    }
+    let time = match humantime::parse_rfc3339_weak(time) {
+        Ok(time) => time.duration_since(UNIX_EPOCH).ok()?,
+        Err(_) => Duration::try_from_secs_f64(time.parse::<f64>().ok()?).ok()?,
+    };
+    Some(Timestamp::new(uhlc::NTP64::from(time), id))
+}
+
+/// Retrieves the time range of a history query from the selector parameters, if any.
+fn get_time_range(parameters: &str) -> ZResult<Option<TimeRange<SystemTime>>> {
+    let time_range = parameters
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix(TIME_PARAMETER)?.strip_prefix('='));
+    match time_range {
+        Some(time_range) => {
+            let time_range = TimeRange::<TimeExpr>::from_str(time_range)
+                .map_err(|e| zerror!("Invalid `{TIME_PARAMETER}` parameter '{time_range}': {e}"))?;
+            Ok(Some(time_range.resolve_at(SystemTime::now())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns true if the interval going from `start` to `end` (unbounded if None) intersects the
+/// time range.
+fn intersects(
+    time_range: &TimeRange<SystemTime>,
+    start: SystemTime,
+    end: Option<SystemTime>,
+) -> bool {
+    let starts_before_range_end = match &time_range.1 {
+        TimeBound::Inclusive(bound) => start <= *bound,
+        TimeBound::Exclusive(bound) => start < *bound,
+        TimeBound::Unbounded => true,
+    };
+    let ends_after_range_start = match (&time_range.0, end) {
+        (_, None) | (TimeBound::Unbounded, _) => true,
+        (TimeBound::Inclusive(bound), Some(end)) | (TimeBound::Exclusive(bound), Some(end)) => {
+            end > *bound
+        }
+    };
+    starts_before_range_end && ends_after_range_start
+}
+
+/// Retrieves the versions of the object associated to the key which were alive during the time
+/// range specified: a version is alive from its timestamp until the timestamp of the next
+/// version of the object or of its deletion.
+///
+/// The timestamps of the versions are only retrieved for the versions which may be alive during
+/// the time range according to their `LastModified` date, and their content for the ones which
+/// are.
+async fn get_stored_versions(
+    client: &S3Client,
+    key: &str,
+    time_range: TimeRange<SystemTime>,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Vec<StoredData>> {
+    let (versions, delete_markers) = client.list_object_versions(key).await?;
+    stored_versions(client, key, &versions, &delete_markers, time_range, foreign).await
+}
+
+/// Retrieves the versions alive during the time range specified out of the versions and delete
+/// markers of the object associated to the key, see [get_stored_versions].
+async fn stored_versions(
+    client: &S3Client,
+    key: &str,
+    versions: &[ObjectVersion],
+    delete_markers: &[DeleteMarkerEntry],
+    time_range: TimeRange<SystemTime>,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<Vec<StoredData>> {
+    // Events on the key sorted by `LastModified` date. The version id is None for delete markers.
+    // The versions are listed newest first and precede the delete markers of the same date.
+    let mut events: Vec<(SystemTime, Option<&str>)> = versions
+        .iter()
+        .rev()
+        .filter_map(|version| {
+            Some((
+                system_time_from_date_time(version.last_modified()?),
+                Some(version.version_id()?),
+            ))
+        })
+        .chain(delete_markers.iter().filter_map(|delete_marker| {
+            Some((
+                system_time_from_date_time(delete_marker.last_modified()?),
+                None,
+            ))
+        }))
+        .collect();
+    events.sort_by_key(|(last_modified, _)| *last_modified);
+
+    // The timestamp of an event isn't later than its `LastModified` date, give or take the clock
+    // skew, hence the versions followed by an event prior to the time range can't be alive during
+    // it. The timestamp of the event following a version which may be is still needed.
+    let range_start = match &time_range.0 {
+        TimeBound::Inclusive(start) | TimeBound::Exclusive(start) => Some(*start),
+        TimeBound::Unbounded => None,
+    };
+    let superseded = |i: usize| match (events.get(i + 1), range_start) {
+        (Some((next, _)), Some(start)) => *next + MAX_TIMESTAMP_SKEW < start,
+        _ => false,
+    };
+    let needed = (0..events.len())
+        .map(|i| !superseded(i) || (i > 0 && !superseded(i - 1)))
+        .collect::<Vec<_>>();
+
+    // Events on the key sorted by timestamp. The version id is None for deletions, i.e. tombstones
+    // and delete markers, the ones following a tombstone being redundant.
+    let mut timeline: Vec<(SystemTime, Option<&str>)> = vec![];
+    let mut after_tombstone = false;
+    for ((last_modified, version_id), needed) in events.iter().zip(needed) {
+        let version_id = match version_id {
+            Some(version_id) if needed => version_id,
+            Some(_) => {
+                after_tombstone = false;
+                continue;
+            }
+            None => {
+                if !after_tombstone && needed {
+                    timeline.push((*last_modified, None));
                 }
-                Err(zerror!("Get operation failed for key '{key}': {e}"))
+                after_tombstone = false;
+                continue;
+            }
+        };
+        let head = client.get_head_object_version(key, version_id).await?;
+        let timestamp = object_timestamp(key, head.metadata(), head.last_modified(), foreign).ok();
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => {
+                log::debug!("Skipping version '{version_id}' of '{key}' without timestamp.");
+                after_tombstone = false;
+                continue;
             }
-        }?;
+        };
+        after_tombstone = head.metadata().map_or(false, is_tombstone);
+        timeline.push((
+            timestamp.get_time().to_system_time(),
+            (!after_tombstone).then_some(*version_id),
+        ));
+    }
+    timeline.sort_by_key(|(time, _)| *time);
 
-        let metadata = output_result
-            .metadata
-            .as_ref()
-            .ok_or_else(|| zerror!("Unable to retrieve metadata."))?;
-        let timestamp = metadata
-            .get(TIMESTAMP_METADATA_KEY)
-            .ok_or_else(|| zerror!("Unable to retrieve timestamp."))?;
-        let timestamp = Timestamp::from_str(timestamp.as_str())
-            .map_err(|e| zerror!("Unable to obtain timestamp for key: {}. {:?}", key, e))?;
-
-        let encoding = output_result.content_encoding().map(|x| x.to_string());
-        let bytes = output_result
-            .body
-            .collect()
-            .await
-            .map(|data| data.into_bytes())
-            .map_err(|e| {
-                zerror!("Get operation failed. Couldn't process retrieved contents: {e}")
-            })?;
-
-        let value = match encoding {
-            Some(encoding) => Encoding::try_from(encoding).map_or_else(
-                |_| Value::from(Vec::from(bytes.to_owned())),
-                |result| Value::from(Vec::from(bytes.to_owned())).encoding(result),
-            ),
-            None => Value::from(Vec::from(bytes)),
+    let mut stored_data = vec![];
+    for (i, (start, version_id)) in timeline.iter().enumerate() {
+        let version_id = match version_id {
+            Some(version_id) => version_id,
+            None => continue,
         };
-        Ok(Some((timestamp, value)))
+        let end = timeline.get(i + 1).map(|(end, _)| *end);
+        if !intersects(&time_range, *start, end) {
+            continue;
+        }
+        let output = client.get_object_version(key, version_id).await?;
+        if let Some((timestamp, value)) = read_stored_value(key, output, foreign).await? {
+            stored_data.push(StoredData { value, timestamp });
+        }
     }
+    Ok(stored_data)
+}
+
+/// Deletes the object associated to the key from a versioned bucket. As the `LastModified` date
+/// of delete markers is too coarse to order the deletion among the versions, a tombstone version
+/// holding the timestamp of the deletion is written before the delete marker.
+async fn delete_versioned_object(
+    client: &S3Client,
+    key: String,
+    timestamp: &Timestamp,
+) -> ZResult<()> {
+    let metadata = HashMap::from([
+        (TIMESTAMP_METADATA_KEY.to_string(), timestamp.to_string()),
+        (TOMBSTONE_METADATA_KEY.to_string(), true.to_string()),
+    ]);
+    let tombstone = client
+        .put_object(
+            key.to_owned(),
+            Value::from(Vec::<u8>::new()),
+            Some(metadata),
+            PutObjectOptions::default(),
+        )
+        .await?;
+    if let Err(e) = client.delete_object(key.to_owned()).await {
+        // Removing the tombstone restores the previous version of the object.
+        if let Some(version_id) = tombstone.version_id() {
+            if let Err(e) = client.remove_object_version(&key, version_id).await {
+                log::warn!("Unable to remove tombstone of '{}': {}", key, e);
+            }
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Builds the lifecycle rule backing the `ttl` of the storage, if a `ttl` applies to all its keys.
+/// As the rule expires all the objects of the bucket, it uses the longest `ttl` (rounded up to
+/// days) so that no object is removed before the expiry of its key, the shorter ones being
+/// enforced by the TTL sweeper.
+fn ttl_lifecycle_rule(ttl: &KeyExprRules<Duration>) -> Option<LifecycleRuleConfig> {
+    ttl.iter().find(|(key_expr, _)| key_expr.as_str() == "**")?;
+    let longest = ttl.iter().map(|(_, ttl)| *ttl).max()?;
+    let days = ((longest.as_secs() + 86399) / 86400) as i32;
+    Some(LifecycleRuleConfig::expiration(TTL_LIFECYCLE_RULE_ID, days))
+}
+
+/// Enables the versioning of a newly created bucket, or verifies it is enabled on a reused one.
+async fn setup_bucket_versioning(client: &S3Client, bucket_created: bool) -> ZResult<()> {
+    if bucket_created {
+        client.enable_bucket_versioning().await?;
+        log::debug!("Versioning enabled on bucket '{}'.", client);
+        Ok(())
+    } else if client.bucket_versioning_is_enabled().await? {
+        Ok(())
+    } else {
+        Err(zerror!(
+            "Versioning is not enabled on reused bucket '{}', it's required by the `versioning` \
+                option of the storage.",
+            client
+        )
+        .into())
+    }
+}
+
+/// Applies the lifecycle rules to a newly created bucket, or compares them to the ones of a
+/// reused bucket, reconciling them if requested. Returns the lifecycle status to be reported in
+/// the admin space.
+async fn setup_bucket_lifecycle(
+    client: &S3Client,
+    rules: Vec<LifecycleRuleConfig>,
+    bucket_created: bool,
+    reconcile: bool,
+) -> ZResult<serde_json::Value> {
+    if bucket_created {
+        client.put_bucket_lifecycle(&rules).await?;
+        log::debug!("Lifecycle rules applied on bucket '{}'.", client);
+        return Ok(serde_json::json!({ "drift": [], "reconciled": false }));
+    }
+
+    let installed_rules = match client.get_bucket_lifecycle().await {
+        Ok(installed_rules) => installed_rules,
+        Err(e) => {
+            log::warn!(
+                "Unable to check lifecycle rules of bucket '{}': {}",
+                client,
+                e
+            );
+            return Ok(serde_json::json!({ "error": e.to_string() }));
+        }
+    };
+    let drift = lifecycle_drift(&rules, &installed_rules);
+    let reconciled = !drift.is_empty() && reconcile;
+    if reconciled {
+        if let Err(e) = client.put_bucket_lifecycle(&rules).await {
+            log::warn!(
+                "Unable to reconcile lifecycle rules of bucket '{}', they differ from the \
+                    configured ones: {:?}: {}",
+                client,
+                drift,
+                e
+            );
+            return Ok(serde_json::json!({
+                "drift": drift,
+                "reconciled": false,
+                "error": e.to_string(),
+            }));
+        }
+        log::debug!("Lifecycle rules of bucket '{}' reconciled.", client);
+    } else if !drift.is_empty() {
+        log::warn!(
+            "Lifecycle rules of bucket '{}' differ from the configured ones: {:?}",
+            client,
+            drift
+        );
+    }
+    Ok(serde_json::json!({ "drift": drift, "reconciled": reconciled }))
+}
+
+/// Periodically deletes the expired objects from the bucket.
+async fn run_ttl_sweeper(client: Arc<S3Client>, ttl: KeyExprRules<Duration>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = delete_expired_objects(&client, &ttl).await {
+            log::warn!(
+                "Error while deleting expired objects of '{}': {}",
+                client,
+                e
+            );
+        }
+    }
+}
+
+/// Deletes the expired objects of the bucket. As the expiry of the objects is only held by their
+/// metadata, only the objects which may have expired according to their `LastModified` date and
+/// the `ttl` of their key are checked, with at most [MAX_CONCURRENT_SWEEP_REQUESTS] HEAD requests
+/// in flight. Objects whose key matches no `ttl` are left to the queries, which hide them once
+/// expired.
+async fn delete_expired_objects(client: &S3Client, ttl: &KeyExprRules<Duration>) -> ZResult<()> {
+    let now = SystemTime::now();
+    let candidates = client
+        .list_objects_in_bucket()
+        .await?
+        .into_iter()
+        .filter(|object| {
+            let (key, last_modified) = match (object.key(), object.last_modified()) {
+                (Some(key), Some(last_modified)) => (key, last_modified),
+                _ => return false,
+            };
+            let ttl = match keyexpr::new(key.trim_start_matches('/'))
+                .ok()
+                .and_then(|key| ttl.lookup(key))
+            {
+                Some(ttl) => ttl,
+                None => return false,
+            };
+            system_time_from_date_time(last_modified) + *ttl <= now
+        })
+        .collect::<Vec<_>>();
+
+    let expired_objects = futures::stream::iter(candidates)
+        .map(|object| async move {
+            let key = object.key().unwrap_or_default();
+            match client.get_head_object(key).await {
+                Ok(head) if head.metadata().map_or(false, is_expired) => Some(object),
+                Ok(_) => None,
+                Err(e) => {
+                    log::debug!("Unable to get head object of '{}': {}", key, e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_SWEEP_REQUESTS)
+        .filter_map(futures::future::ready)
+        .collect::<Vec<_>>()
+        .await;
+    for batch in expired_objects.chunks(MAX_DELETE_OBJECTS_BATCH) {
+        client.delete_objects_in_bucket(batch.to_vec()).await?;
+        log::debug!("Deleted {} expired objects from '{}'.", batch.len(), client);
+    }
+    Ok(())
 }
 
 impl Drop for S3Storage {
     fn drop(&mut self) {
-        match self.config.on_closure {
-            config::OnClosure::DestroyBucket => {
-                let client2 = self.client.clone();
-                async_std::task::spawn(async move {
-                    client2.delete_bucket().await.map_or_else(
-                        |e| {
-                            log::debug!(
-                                "Error while closing S3 storage '{}': {}",
-                                client2,
-                                e.to_string()
-                            )
-                        },
-                        |_| log::debug!("Closing S3 storage '{}'", client2),
+        if let Some(ttl_sweeper) = &self.ttl_sweeper {
+            ttl_sweeper.abort();
+        }
+        if let Some(change_detector) = &self.change_detector {
+            change_detector.abort();
+        }
+        if let Some(disk_reconciler) = &self.disk_reconciler {
+            disk_reconciler.abort();
+        }
+        if let Some(fsck_server) = &self.fsck_server {
+            fsck_server.abort();
+        }
+        if let config::OnClosure::DoNothing = self.config.on_closure {
+            log::debug!(
+                "Close S3 storage, keeping bucket '{}' as it is.",
+                self.client
+            );
+            return;
+        }
+
+        // The closure operation runs on the runtime of the volume, up to `on_closure_timeout`.
+        // The thread dropping the storage, which may be a worker of the storage manager, only
+        // waits for it up to `ON_CLOSURE_WAIT`: the router isn't stalled by long operations, which
+        // report their failure in the admin status of the volume once over.
+        let client = self.client.clone();
+        let on_closure = self.config.on_closure.clone();
+        let foreign = self.config.adopt_foreign_objects.clone();
+        let timeout = self.config.on_closure_timeout;
+        let closure_failures = self.closure_failures.clone();
+        let (done_sender, done_receiver) = std::sync::mpsc::channel();
+        self.runtime.spawn(async move {
+            let result = tokio::time::timeout(
+                timeout,
+                run_on_closure(&client, &on_closure, foreign.as_ref()),
+            )
+            .await
+            .unwrap_or_else(|_| Err(zerror!("timed out after {:?}", timeout).into()));
+            match result {
+                Ok(()) => log::debug!("Closing S3 storage '{}'", client),
+                Err(e) => {
+                    log::warn!("Error while closing S3 storage '{}': {}", client, e);
+                    closure_failures.lock().unwrap().insert(
+                        client.to_string(),
+                        serde_json::json!({
+                            "error": e.to_string(),
+                            "time": humantime::format_rfc3339_seconds(SystemTime::now())
+                                .to_string(),
+                        }),
                     );
-                });
-            }
-            config::OnClosure::DoNothing => {
-                log::debug!(
-                    "Close S3 storage, keeping bucket '{}' as it is.",
-                    self.client
-                );
+                }
             }
+            let _ = done_sender.send(());
+        });
+        if done_receiver.recv_timeout(ON_CLOSURE_WAIT).is_err() {
+            log::info!(
+                "Closure operation of S3 storage '{}' still running after {:?}, it goes on in the \
+                    background for up to {:?}.",
+                self.client,
+                ON_CLOSURE_WAIT,
+                timeout
+            );
+        }
+    }
+}
+
+/// Performs the `on_closure` operation of a storage on its bucket.
+async fn run_on_closure(
+    client: &S3Client,
+    on_closure: &config::OnClosure,
+    foreign: Option<&ForeignObjectsConfig>,
+) -> ZResult<()> {
+    match on_closure {
+        config::OnClosure::DestroyBucket => client.delete_bucket().await,
+        config::OnClosure::EmptyBucket => client.empty_bucket().await,
+        config::OnClosure::Archive(archive) => {
+            archive_objects(client, archive).await?;
+            client.delete_bucket().await
+        }
+        config::OnClosure::Snapshot => {
+            let mut manifest = vec![];
+            let count = snapshot::write_manifest(client, foreign, &mut manifest).await?;
+            client
+                .put_object(
+                    MANIFEST_KEY.to_owned(),
+                    Value::from(manifest),
+                    None,
+                    PutObjectOptions::default(),
+                )
+                .await?;
+            log::debug!("Wrote manifest of {} samples into '{}'.", count, client);
+            Ok(())
         }
+        config::OnClosure::DoNothing => Ok(()),
+    }
+}
+
+/// Copies all the objects of the bucket to the archive bucket, under the archive prefix if any.
+async fn archive_objects(client: &S3Client, archive: &config::ArchiveConfig) -> ZResult<()> {
+    let objects = client.list_objects_in_bucket().await?;
+    for object in objects.iter() {
+        let key = match object.key() {
+            Some(key) => key,
+            None => continue,
+        };
+        let target_key = match &archive.prefix {
+            Some(prefix) => format!("{prefix}{key}"),
+            None => key.to_owned(),
+        };
+        client
+            .copy_object_to(key, object.size(), &archive.bucket, &target_key)
+            .await
+            .map_err(|e| zerror!("Couldn't archive '{}': {}", key, e))?;
+    }
+    log::debug!(
+        "Archived {} objects of '{}' into '{}'.",
+        objects.len(),
+        client,
+        archive.bucket
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ttl_lifecycle_rule_uses_the_longest_ttl() {
+        let ttl = |rules: Vec<(&str, u64)>| {
+            KeyExprRules::new(
+                rules
+                    .into_iter()
+                    .map(|(key_expr, secs)| {
+                        (
+                            OwnedKeyExpr::try_from(key_expr).unwrap(),
+                            Duration::from_secs(secs),
+                        )
+                    })
+                    .collect(),
+            )
+        };
+        let rule = ttl_lifecycle_rule(&ttl(vec![("**", 86400), ("archive/**", 2592000)]));
+        assert_eq!(rule.and_then(|rule| rule.expiration_days), Some(30));
+        let rule = ttl_lifecycle_rule(&ttl(vec![("**", 3600)]));
+        assert_eq!(rule.and_then(|rule| rule.expiration_days), Some(1));
+        assert!(ttl_lifecycle_rule(&ttl(vec![("archive/**", 86400)])).is_none());
+    }
+
+    #[test]
+    fn metrics_endpoint_defaults_to_loopback() {
+        let addr = parse_metrics_endpoint("9464").unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 9464);
+        let addr = parse_metrics_endpoint("0.0.0.0:9464").unwrap();
+        assert!(addr.ip().is_unspecified());
+        assert!(parse_metrics_endpoint("localhost").is_err());
     }
 }
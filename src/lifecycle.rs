@@ -0,0 +1,345 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+
+use aws_sdk_s3::model::{
+    AbortIncompleteMultipartUpload, ExpirationStatus, LifecycleExpiration, LifecycleRule,
+    LifecycleRuleFilter, NoncurrentVersionExpiration, Transition, TransitionStorageClass,
+};
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+use crate::properties::{LifecycleProperties, LifecycleRuleProperties};
+use crate::utils::S3Key;
+
+// Lifecycle properties
+pub const LIFECYCLE_PROP: &str = "lifecycle";
+
+/// Lifecycle configuration of the bucket of a storage, declared under the `lifecycle` field of
+/// the storage configuration:
+///
+/// ```
+/// lifecycle: {
+///   reconcile: true,
+///   rules: [
+///     {
+///       id: "archive",
+///       prefix: "archive/",
+///       expiration_days: 365,
+///       transitions: [{ days: 30, storage_class: "STANDARD_IA" }],
+///       abort_incomplete_multipart_upload_days: 7,
+///     },
+///   ],
+/// }
+/// ```
+///
+/// The `prefix` of the rules is relative to the `strip_prefix` of the storage, as its keys: it's
+/// mapped to the names of the objects, which start with a '/' when no `strip_prefix` is set.
+///
+/// The rules are applied on newly created buckets. When `reconcile` is true, the rules are also
+/// applied on reused buckets whose lifecycle configuration differs from the declared one. A
+/// failure to do so is reported in the admin status of the storage without preventing its
+/// creation.
+#[derive(Clone, Default)]
+pub(crate) struct LifecycleConfig {
+    pub rules: Vec<LifecycleRuleConfig>,
+    pub reconcile: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LifecycleRuleConfig {
+    pub id: String,
+    pub prefix: String,
+    pub expiration_days: Option<i32>,
+    pub noncurrent_version_expiration_days: Option<i32>,
+    pub transitions: Vec<TransitionConfig>,
+    pub abort_incomplete_multipart_upload_days: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TransitionConfig {
+    pub days: i32,
+    pub storage_class: String,
+}
+
+impl LifecycleConfig {
+    /// Creates a new instance of [LifecycleConfig] from the `lifecycle` field of the storage
+    /// configuration.
+    pub fn new(properties: LifecycleProperties) -> ZResult<Self> {
+        let rules = properties
+            .rules
+            .into_iter()
+            .map(LifecycleRuleConfig::new)
+            .collect::<ZResult<Vec<_>>>()?;
+        Ok(LifecycleConfig {
+            rules,
+            reconcile: properties.reconcile,
+        })
+    }
+
+    /// Maps the `prefix` of the rules, relative to the `strip_prefix` of the storage, to the
+    /// names of the objects of the bucket.
+    pub fn with_path_prefix(mut self, path_prefix: &Option<String>) -> Self {
+        for rule in self.rules.iter_mut().filter(|rule| !rule.prefix.is_empty()) {
+            rule.prefix = S3Key::from_key(path_prefix.to_owned(), rule.prefix.to_owned()).into();
+        }
+        self
+    }
+}
+
+impl LifecycleRuleConfig {
+    fn new(properties: LifecycleRuleProperties) -> ZResult<Self> {
+        let id = properties.id;
+        if id.is_empty() {
+            return Err(
+                zerror!("Lifecycle rules of `{LIFECYCLE_PROP}` must have a non empty id").into(),
+            );
+        }
+        let transitions = properties
+            .transitions
+            .into_iter()
+            .map(|transition| {
+                Ok(TransitionConfig {
+                    days: to_days(transition.days, &id)?,
+                    storage_class: transition.storage_class,
+                })
+            })
+            .collect::<ZResult<Vec<_>>>()?;
+        let rule = LifecycleRuleConfig {
+            expiration_days: properties
+                .expiration_days
+                .map(|days| to_days(days, &id))
+                .transpose()?,
+            noncurrent_version_expiration_days: properties
+                .noncurrent_version_expiration_days
+                .map(|days| to_days(days, &id))
+                .transpose()?,
+            abort_incomplete_multipart_upload_days: properties
+                .abort_incomplete_multipart_upload_days
+                .map(|days| to_days(days, &id))
+                .transpose()?,
+            id,
+            prefix: properties.prefix,
+            transitions,
+        };
+        if rule.expiration_days.is_none()
+            && rule.noncurrent_version_expiration_days.is_none()
+            && rule.transitions.is_empty()
+            && rule.abort_incomplete_multipart_upload_days.is_none()
+        {
+            return Err(zerror!("Lifecycle rule '{}' doesn't declare any action", rule.id).into());
+        }
+        Ok(rule)
+    }
+
+    /// Creates a rule expiring all the objects of the bucket after the amount of days specified.
+    pub fn expiration(id: &str, days: i32) -> Self {
+        LifecycleRuleConfig {
+            id: id.to_owned(),
+            prefix: String::new(),
+            expiration_days: Some(days),
+            noncurrent_version_expiration_days: None,
+            transitions: vec![],
+            abort_incomplete_multipart_upload_days: None,
+        }
+    }
+
+    /// Builds the S3 lifecycle rule corresponding to this configuration.
+    pub fn to_lifecycle_rule(&self) -> LifecycleRule {
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|transition| {
+                Transition::builder()
+                    .days(transition.days)
+                    .storage_class(TransitionStorageClass::from(
+                        transition.storage_class.as_str(),
+                    ))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        LifecycleRule::builder()
+            .id(&self.id)
+            .status(ExpirationStatus::Enabled)
+            .filter(LifecycleRuleFilter::Prefix(self.prefix.to_owned()))
+            .set_expiration(
+                self.expiration_days
+                    .map(|days| LifecycleExpiration::builder().days(days).build()),
+            )
+            .set_noncurrent_version_expiration(self.noncurrent_version_expiration_days.map(
+                |days| {
+                    NoncurrentVersionExpiration::builder()
+                        .noncurrent_days(days)
+                        .build()
+                },
+            ))
+            .set_transitions((!transitions.is_empty()).then_some(transitions))
+            .set_abort_incomplete_multipart_upload(self.abort_incomplete_multipart_upload_days.map(
+                |days| {
+                    AbortIncompleteMultipartUpload::builder()
+                        .days_after_initiation(days)
+                        .build()
+                },
+            ))
+            .build()
+    }
+
+    /// Retrieves the configuration corresponding to an S3 lifecycle rule, as returned by the
+    /// server, in order to compare it with the declared one.
+    pub fn from_lifecycle_rule(rule: &LifecycleRule) -> Self {
+        let prefix = match rule.filter() {
+            Some(LifecycleRuleFilter::Prefix(prefix)) => prefix.to_owned(),
+            _ => String::new(),
+        };
+        let mut transitions = rule
+            .transitions()
+            .unwrap_or_default()
+            .iter()
+            .map(|transition| TransitionConfig {
+                days: transition.days(),
+                storage_class: transition
+                    .storage_class()
+                    .map(|class| class.as_str().to_owned())
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        transitions.sort();
+        LifecycleRuleConfig {
+            id: rule.id().unwrap_or_default().to_owned(),
+            prefix,
+            expiration_days: rule
+                .expiration()
+                .map(|expiration| expiration.days())
+                .filter(|days| *days > 0),
+            noncurrent_version_expiration_days: rule
+                .noncurrent_version_expiration()
+                .map(|expiration| expiration.noncurrent_days())
+                .filter(|days| *days > 0),
+            transitions,
+            abort_incomplete_multipart_upload_days: rule
+                .abort_incomplete_multipart_upload()
+                .map(|abort| abort.days_after_initiation())
+                .filter(|days| *days > 0),
+        }
+    }
+}
+
+/// Converts an amount of days of a lifecycle rule into the type used by the S3 API.
+fn to_days(days: NonZeroU32, rule_id: &str) -> ZResult<i32> {
+    i32::try_from(days.get())
+        .map_err(|_| zerror!("Lifecycle rule '{rule_id}' declares too many days: {days}").into())
+}
+
+/// Compares the declared lifecycle rules with the ones currently installed on the bucket,
+/// returning a description of each difference found.
+pub(crate) fn lifecycle_drift(
+    declared: &[LifecycleRuleConfig],
+    installed: &[LifecycleRule],
+) -> Vec<String> {
+    let installed = installed
+        .iter()
+        .map(LifecycleRuleConfig::from_lifecycle_rule)
+        .collect::<Vec<_>>();
+    let mut drift = vec![];
+    for rule in declared {
+        let mut rule = rule.to_owned();
+        rule.transitions.sort();
+        match installed.iter().find(|installed| installed.id == rule.id) {
+            None => drift.push(format!("Rule '{}' is not installed.", rule.id)),
+            Some(installed) if installed != &rule => drift.push(format!(
+                "Rule '{}' differs: declared {:?}, installed {:?}.",
+                rule.id, rule, installed
+            )),
+            Some(_) => {}
+        }
+    }
+    for rule in installed {
+        if !declared.iter().any(|declared| declared.id == rule.id) {
+            drift.push(format!("Rule '{}' is installed but not declared.", rule.id));
+        }
+    }
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, prefix: &str, expiration_days: i32) -> LifecycleRuleConfig {
+        LifecycleRuleConfig {
+            prefix: prefix.to_owned(),
+            ..LifecycleRuleConfig::expiration(id, expiration_days)
+        }
+    }
+
+    #[test]
+    fn no_drift_when_the_installed_rules_match() {
+        let mut declared = rule("archive", "archive/", 365);
+        declared.transitions = vec![
+            TransitionConfig {
+                days: 90,
+                storage_class: "GLACIER".to_owned(),
+            },
+            TransitionConfig {
+                days: 30,
+                storage_class: "STANDARD_IA".to_owned(),
+            },
+        ];
+        let declared = vec![declared, rule("tmp", "tmp/", 1)];
+        let installed = declared
+            .iter()
+            .map(LifecycleRuleConfig::to_lifecycle_rule)
+            .collect::<Vec<_>>();
+        assert!(lifecycle_drift(&declared, &installed).is_empty());
+    }
+
+    #[test]
+    fn drift_reports_differing_missing_and_undeclared_rules() {
+        let declared = vec![rule("archive", "archive/", 365), rule("tmp", "tmp/", 1)];
+        let installed = vec![
+            rule("archive", "archive/", 30).to_lifecycle_rule(),
+            rule("legacy", "", 7).to_lifecycle_rule(),
+        ];
+        let drift = lifecycle_drift(&declared, &installed);
+        assert_eq!(drift.len(), 3);
+        assert!(drift[0].starts_with("Rule 'archive' differs"));
+        assert_eq!(drift[1], "Rule 'tmp' is not installed.");
+        assert_eq!(drift[2], "Rule 'legacy' is installed but not declared.");
+    }
+
+    #[test]
+    fn prefixes_are_mapped_to_object_names() {
+        let config = LifecycleConfig {
+            rules: vec![rule("archive", "archive/", 365), rule("all", "", 730)],
+            reconcile: false,
+        };
+        let prefixes = |config: LifecycleConfig| {
+            config
+                .rules
+                .into_iter()
+                .map(|rule| rule.prefix)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            prefixes(config.clone().with_path_prefix(&None)),
+            vec!["/archive/", ""]
+        );
+        assert_eq!(
+            prefixes(config.with_path_prefix(&Some("s3/example".to_owned()))),
+            vec!["archive/", ""]
+        );
+    }
+}
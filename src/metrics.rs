@@ -0,0 +1,386 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Map, Value};
+
+use crate::error::{S3Error, S3ErrorKind};
+
+// Upper bounds, in seconds, of the buckets of the latency histograms.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct OperationMetrics {
+    requests: u64,
+    errors: u64,
+    retries: u64,
+    /// Amount of requests per latency bucket (not cumulative), the last one being `+Inf`.
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    operations: BTreeMap<&'static str, OperationMetrics>,
+    /// Amount of errors per kind and S3 error code.
+    error_codes: BTreeMap<(S3ErrorKind, String), u64>,
+    bytes_in: u64,
+    bytes_out: u64,
+    last_success: Option<SystemTime>,
+    last_error: Option<Value>,
+    /// Whether the last request failed because the bucket doesn't exist.
+    bucket_missing: bool,
+    last_listing: Option<Value>,
+}
+
+/// Metrics of the S3 requests issued by a client, i.e. by a storage.
+pub(crate) struct Metrics {
+    /// Name of the storage, if the client belongs to one.
+    storage: Option<String>,
+    bucket: String,
+    state: Mutex<MetricsState>,
+}
+
+impl Metrics {
+    pub fn new(storage: Option<String>, bucket: String) -> Arc<Self> {
+        let metrics = Arc::new(Metrics {
+            storage,
+            bucket,
+            state: Mutex::new(MetricsState::default()),
+        });
+        #[cfg(feature = "stats")]
+        registry::register(&metrics);
+        metrics
+    }
+
+    /// Records the outcome of a request of the given type, including its retries.
+    pub fn record(
+        &self,
+        operation: &'static str,
+        latency: Duration,
+        retries: u32,
+        error: Option<&S3Error>,
+    ) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let metrics = state.operations.entry(operation).or_default();
+        metrics.requests += 1;
+        metrics.retries += retries as u64;
+        let latency = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        metrics.latency_buckets[bucket] += 1;
+        metrics.latency_sum += latency;
+        match error {
+            Some(error) => {
+                metrics.errors += 1;
+                if error.kind() == S3ErrorKind::BucketMissing {
+                    state.bucket_missing = true;
+                }
+                state.last_error = Some(json!({
+                    "time": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                    "operation": operation,
+                    "kind": error.kind().as_str(),
+                    "code": error.code(),
+                    "request_id": error.request_id(),
+                    "message": error.to_string(),
+                }));
+                let code = error.code().unwrap_or_default().to_owned();
+                *state.error_codes.entry((error.kind(), code)).or_default() += 1;
+            }
+            None => {
+                state.bucket_missing = false;
+                state.last_success = Some(SystemTime::now());
+            }
+        }
+    }
+
+    /// Records that the bucket turned out to be missing, e.g. after a HEAD request returned a 404
+    /// without the error code telling it.
+    pub fn record_bucket_missing(&self) {
+        self.state.lock().unwrap().bucket_missing = true;
+    }
+
+    /// Records the outcome of a full listing of the bucket.
+    pub fn record_listing(&self, objects: usize, size: u64) {
+        self.state.lock().unwrap().last_listing = Some(json!({
+            "time": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            "objects": objects,
+            "size": size,
+        }));
+    }
+
+    /// Health of the bucket as observed through the requests issued to it, reported in the admin
+    /// status of the storage.
+    pub fn health(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "bucket_exists": !state.bucket_missing,
+            "last_success": state
+                .last_success
+                .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+            "last_error": state.last_error,
+            "last_listing": state.last_listing,
+        })
+    }
+
+    /// Records bytes received from the bucket.
+    pub fn add_bytes_in(&self, bytes: u64) {
+        self.state.lock().unwrap().bytes_in += bytes;
+    }
+
+    /// Records bytes sent to the bucket.
+    pub fn add_bytes_out(&self, bytes: u64) {
+        self.state.lock().unwrap().bytes_out += bytes;
+    }
+
+    /// Snapshot of the metrics, reported in the admin status of the storage.
+    pub fn status(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let operations: Map<String, Value> = state
+            .operations
+            .iter()
+            .map(|(operation, metrics)| {
+                let mut cumulative = 0;
+                let latency_buckets: Map<String, Value> = metrics
+                    .latency_buckets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, count)| {
+                        cumulative += count;
+                        (bucket_label(i), json!(cumulative))
+                    })
+                    .collect();
+                (
+                    (*operation).to_owned(),
+                    json!({
+                        "requests": metrics.requests,
+                        "errors": metrics.errors,
+                        "retries": metrics.retries,
+                        "latency_seconds": {
+                            "sum": metrics.latency_sum,
+                            "buckets": latency_buckets,
+                        },
+                    }),
+                )
+            })
+            .collect();
+        let mut error_kinds: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut error_codes: BTreeMap<&str, u64> = BTreeMap::new();
+        for ((kind, code), count) in state.error_codes.iter() {
+            *error_kinds.entry(kind.as_str()).or_default() += count;
+            if !code.is_empty() {
+                *error_codes.entry(code).or_default() += count;
+            }
+        }
+        json!({
+            "operations": operations,
+            "error_kinds": error_kinds,
+            "error_codes": error_codes,
+            "bytes_in": state.bytes_in,
+            "bytes_out": state.bytes_out,
+        })
+    }
+}
+
+fn bucket_label(i: usize) -> String {
+    LATENCY_BUCKETS
+        .get(i)
+        .map_or_else(|| "+Inf".to_owned(), |bound| bound.to_string())
+}
+
+/// In-process registry of the metrics of all the storages, exposed in the Prometheus text format.
+#[cfg(feature = "stats")]
+pub mod registry {
+    use std::convert::Infallible;
+    use std::fmt::Write;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex, Weak};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response};
+
+    use super::{bucket_label, Metrics};
+
+    lazy_static::lazy_static! {
+        static ref REGISTRY: Mutex<Vec<Weak<Metrics>>> = Mutex::new(vec![]);
+    }
+
+    pub(crate) fn register(metrics: &Arc<Metrics>) {
+        let mut registry = REGISTRY.lock().unwrap();
+        registry.retain(|metrics| metrics.strong_count() > 0);
+        registry.push(Arc::downgrade(metrics));
+    }
+
+    /// Renders the metrics of all the live storages in the Prometheus text exposition format.
+    pub fn prometheus_text() -> String {
+        let metrics: Vec<Arc<Metrics>> = REGISTRY
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        // Storages sharing a bucket (e.g. with different prefixes) are told apart by their name.
+        let states: Vec<_> = metrics
+            .iter()
+            .map(|metrics| {
+                let labels = format!(
+                    "storage=\"{}\",bucket=\"{}\"",
+                    escape_label(metrics.storage.as_deref().unwrap_or_default()),
+                    escape_label(&metrics.bucket)
+                );
+                (labels, metrics.state.lock().unwrap())
+            })
+            .collect();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_requests_total S3 requests issued by the storages.\n\
+            # TYPE zenoh_s3_requests_total counter"
+        );
+        for (labels, state) in states.iter() {
+            for (operation, metrics) in state.operations.iter() {
+                let _ = writeln!(
+                    out,
+                    "zenoh_s3_requests_total{{{labels},operation=\"{operation}\"}} {}",
+                    metrics.requests
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_request_errors_total S3 requests which failed.\n\
+            # TYPE zenoh_s3_request_errors_total counter"
+        );
+        for (labels, state) in states.iter() {
+            for (operation, metrics) in state.operations.iter() {
+                let _ = writeln!(
+                    out,
+                    "zenoh_s3_request_errors_total{{{labels},operation=\"{operation}\"}} \
+                    {}",
+                    metrics.errors
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_request_retries_total Retries of S3 requests.\n\
+            # TYPE zenoh_s3_request_retries_total counter"
+        );
+        for (labels, state) in states.iter() {
+            for (operation, metrics) in state.operations.iter() {
+                let _ = writeln!(
+                    out,
+                    "zenoh_s3_request_retries_total{{{labels},operation=\"{operation}\"}} \
+                    {}",
+                    metrics.retries
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_request_duration_seconds Latency of S3 requests, retries included.\n\
+            # TYPE zenoh_s3_request_duration_seconds histogram"
+        );
+        for (labels, state) in states.iter() {
+            for (operation, metrics) in state.operations.iter() {
+                let mut cumulative = 0;
+                for (i, count) in metrics.latency_buckets.iter().enumerate() {
+                    cumulative += count;
+                    let _ = writeln!(
+                        out,
+                        "zenoh_s3_request_duration_seconds_bucket{{{labels},\
+                        operation=\"{operation}\",le=\"{}\"}} {cumulative}",
+                        bucket_label(i)
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "zenoh_s3_request_duration_seconds_sum{{{labels},\
+                    operation=\"{operation}\"}} {}\n\
+                    zenoh_s3_request_duration_seconds_count{{{labels},\
+                    operation=\"{operation}\"}} {}",
+                    metrics.latency_sum, metrics.requests
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_errors_total S3 errors per kind and error code.\n\
+            # TYPE zenoh_s3_errors_total counter"
+        );
+        for (labels, state) in states.iter() {
+            for ((kind, code), count) in state.error_codes.iter() {
+                let _ = writeln!(
+                    out,
+                    "zenoh_s3_errors_total{{{labels},kind=\"{kind}\",code=\"{code}\"}} \
+                    {count}"
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP zenoh_s3_bytes_total Payload bytes exchanged with the buckets.\n\
+            # TYPE zenoh_s3_bytes_total counter"
+        );
+        for (labels, state) in states.iter() {
+            let _ = writeln!(
+                out,
+                "zenoh_s3_bytes_total{{{labels},direction=\"in\"}} {}\n\
+                zenoh_s3_bytes_total{{{labels},direction=\"out\"}} {}",
+                state.bytes_in, state.bytes_out
+            );
+        }
+        out
+    }
+
+    /// Escapes a label value as required by the Prometheus text format.
+    fn escape_label(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    /// Serves the metrics of all the storages in the Prometheus text exposition format over HTTP.
+    pub(crate) async fn serve(addr: SocketAddr) {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_request| async {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("content-type", "text/plain; version=0.0.4")
+                        .body(Body::from(prometheus_text()))
+                        .unwrap(),
+                )
+            }))
+        });
+        match hyper::Server::try_bind(&addr) {
+            Ok(server) => {
+                log::debug!("Serving S3 metrics on {}.", addr);
+                if let Err(e) = server.serve(make_service).await {
+                    log::warn!("S3 metrics endpoint on {} failed: {}", addr, e);
+                }
+            }
+            Err(e) => log::warn!("Unable to serve S3 metrics on {}: {}", addr, e),
+        }
+    }
+}
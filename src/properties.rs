@@ -0,0 +1,514 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Typed properties of the `volume` field of the S3 storage configurations, from which the
+//! [S3Config](crate::config::S3Config) of the storages is built.
+
+use std::collections::BTreeMap;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+const DEFAULT_ON_CLOSURE_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_TTL_SWEEP_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 32;
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 10;
+const DEFAULT_CACHE_MAX_SIZE: usize = 64 * 1024 * 1024;
+const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+pub(crate) const DEFAULT_DISK_CACHE_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+pub(crate) const DEFAULT_RECONCILE_INTERVAL_SECS: u64 = 10;
+
+// Key expression of the properties applying to all the keys of the storage.
+const ALL_KEYS: &str = "**";
+
+/// Properties of the `volume` field of an S3 storage configuration. Unknown properties are
+/// rejected.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StorageProperties {
+    /// Identifier of the volume of the storage.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Name of the bucket of the storage.
+    pub bucket: String,
+    /// Access key of the credentials, preferably set under `private`.
+    #[serde(default)]
+    pub access_key: Option<String>,
+    /// Secret key of the credentials, preferably set under `private`.
+    #[serde(default)]
+    pub secret_key: Option<String>,
+    /// Credentials of the storage, hidden from the admin space.
+    #[serde(default)]
+    pub private: Option<PrivateProperties>,
+    /// Whether an existing bucket owned by the credentials is associated to the storage instead
+    /// of failing.
+    #[serde(default)]
+    pub reuse_bucket: bool,
+    /// Whether the storage rejects the updates.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Operation performed on the bucket when the storage is closed.
+    #[serde(default)]
+    pub on_closure: OnClosureStrategy,
+    /// Maximum time, in seconds, the `on_closure` operation may take.
+    #[serde(default = "default_on_closure_timeout")]
+    pub on_closure_timeout: NonZeroU64,
+    /// Destination of the objects with the `archive` closure strategy.
+    #[serde(default)]
+    pub archive: Option<ArchiveProperties>,
+    /// Time to live, in seconds, of the samples, either for all the keys or per key expression.
+    #[serde(default)]
+    pub ttl: Option<PerKeyExpr<NonZeroU64>>,
+    /// Period, in seconds, of the deletion of the expired objects.
+    #[serde(default = "default_ttl_sweep_interval")]
+    pub ttl_sweep_interval: NonZeroU64,
+    /// Whether a lifecycle rule expiring the objects after the longest `ttl` is installed.
+    #[serde(default)]
+    pub ttl_lifecycle_rule: bool,
+    /// Lifecycle rules of the bucket, see [LifecycleConfig](crate::lifecycle::LifecycleConfig).
+    #[serde(default)]
+    pub lifecycle: Option<LifecycleProperties>,
+    /// Storage class of the written objects, either for all the keys or per key expression.
+    #[serde(default)]
+    pub storage_class: Option<PerKeyExpr<String>>,
+    /// Tags of the written objects per key expression, as tag names associated to templates.
+    #[serde(default)]
+    pub tags: BTreeMap<String, BTreeMap<String, String>>,
+    /// Whether the versioning of the bucket is enabled, to serve history queries.
+    #[serde(default)]
+    pub versioning: bool,
+    /// Consistency check of the bucket, run on storage creation or on demand.
+    #[serde(default)]
+    pub fsck: Option<FsckProperties>,
+    /// Policy applied to the objects lacking timestamp metadata.
+    #[serde(default)]
+    pub adopt_foreign_objects: Option<ForeignObjectsProperties>,
+    /// Zenoh endpoints the session of the storage connects to, used by `change_detection` and
+    /// `fsck.admin_queryable`.
+    #[serde(default)]
+    pub connect: Vec<String>,
+    /// Detection of the changes made to the bucket by other tools, see
+    /// [ChangeDetectionConfig](crate::change_detection::ChangeDetectionConfig).
+    #[serde(default)]
+    pub change_detection: Option<ChangeDetectionProperties>,
+    /// In-memory cache of the samples, see [CacheConfig](crate::cache::CacheConfig).
+    #[serde(default)]
+    pub cache: Option<CacheProperties>,
+    /// On-disk cache of the samples, see [DiskCacheConfig](crate::disk_cache::DiskCacheConfig).
+    #[serde(default)]
+    pub disk_cache: Option<DiskCacheProperties>,
+    /// Settings of the bucket created by the storage, see
+    /// [BucketCreationConfig](crate::bucket_creation::BucketCreationConfig).
+    #[serde(default)]
+    pub bucket_creation: Option<BucketCreationProperties>,
+    /// Object Lock settings per key expression, see
+    /// [RetentionPolicy](crate::retention::RetentionPolicy).
+    #[serde(default)]
+    pub retention: BTreeMap<String, RetentionProperties>,
+    /// Maximum amount of S3 requests of the storage in flight at the same time.
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: NonZeroUsize,
+    /// Maximum rates of the requests per type of request, see
+    /// [RateLimitConfig](crate::rate_limit::RateLimitConfig).
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitProperties>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PrivateProperties {
+    #[serde(default)]
+    pub access_key: Option<String>,
+    #[serde(default)]
+    pub secret_key: Option<String>,
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnClosureStrategy {
+    DestroyBucket,
+    EmptyBucket,
+    Archive,
+    Snapshot,
+    #[default]
+    DoNothing,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveProperties {
+    /// Bucket the objects are copied into.
+    pub bucket: String,
+    /// Prefix prepended to the keys of the copied objects.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FsckProperties {
+    /// Zenoh ID (hexadecimal) used to stamp the objects lacking a valid timestamp.
+    #[serde(default)]
+    pub repair_zid: Option<String>,
+    /// Whether the check is run when the storage is created.
+    #[serde(default = "default_true")]
+    pub on_start: bool,
+    /// Whether the report can be queried, and the check run on request, on the admin key of the
+    /// storage.
+    #[serde(default)]
+    pub admin_queryable: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ForeignObjectsProperties {
+    #[serde(default)]
+    pub timestamp_source: TimestampSourceName,
+    /// Metadata holding the time of the objects, required for the `metadata` source.
+    #[serde(default)]
+    pub metadata_key: Option<String>,
+    /// Zenoh ID (hexadecimal) of the derived timestamps.
+    pub zid: String,
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSourceName {
+    #[default]
+    LastModified,
+    Metadata,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LifecycleProperties {
+    /// Lifecycle rules of the bucket.
+    #[serde(default)]
+    pub rules: Vec<LifecycleRuleProperties>,
+    /// Whether the rules are also applied on reused buckets whose rules differ.
+    #[serde(default)]
+    pub reconcile: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LifecycleRuleProperties {
+    /// Identifier of the rule, unique within the bucket.
+    pub id: String,
+    /// Prefix (relative to `strip_prefix`) of the keys the rule applies to, all by default.
+    #[serde(default)]
+    pub prefix: String,
+    /// Days after which the objects expire.
+    #[serde(default)]
+    pub expiration_days: Option<NonZeroU32>,
+    /// Days after which the former versions of the objects expire.
+    #[serde(default)]
+    pub noncurrent_version_expiration_days: Option<NonZeroU32>,
+    /// Transitions of the objects to other storage classes.
+    #[serde(default)]
+    pub transitions: Vec<TransitionProperties>,
+    /// Days after which the incomplete multipart uploads are aborted.
+    #[serde(default)]
+    pub abort_incomplete_multipart_upload_days: Option<NonZeroU32>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionProperties {
+    /// Days after which the objects are transitioned.
+    pub days: NonZeroU32,
+    /// Storage class the objects are transitioned to, e.g. "STANDARD_IA".
+    pub storage_class: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChangeDetectionProperties {
+    /// Either listing the bucket periodically or receiving its S3 event notifications.
+    #[serde(default)]
+    pub mode: ChangeDetectionMode,
+    /// Period, in seconds, of the listings of the bucket in `poll` mode.
+    #[serde(default = "default_poll_interval")]
+    pub interval: NonZeroU64,
+    /// Address the webhook receiving the event notifications binds to, required in `events`
+    /// mode.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Token the event notifications must hold in their `Authorization` header.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeDetectionMode {
+    #[default]
+    Poll,
+    Events,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CacheProperties {
+    /// Maximum size, in bytes, of the cached keys and payloads.
+    #[serde(default = "default_cache_max_size")]
+    pub max_size: usize,
+    /// Time, in seconds, after which a cached sample is revalidated.
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+    /// Key expressions (relative to `strip_prefix`) of the cached keys, all by default.
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DiskCacheProperties {
+    /// Directory holding the cached samples.
+    pub path: String,
+    /// Maximum size, in bytes, of the cached samples.
+    #[serde(default = "default_disk_cache_max_size")]
+    pub max_size: u64,
+    /// Period, in seconds, of the writing of the pending samples into the bucket.
+    #[serde(default = "default_reconcile_interval")]
+    pub reconcile_interval: NonZeroU64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BucketCreationProperties {
+    /// Whether S3 Object Lock is enabled on the bucket.
+    #[serde(default)]
+    pub object_lock: bool,
+    /// Canned ACL of the bucket, e.g. "private".
+    #[serde(default)]
+    pub acl: Option<String>,
+    /// Object ownership controls of the bucket, e.g. "BucketOwnerEnforced".
+    #[serde(default)]
+    pub object_ownership: Option<String>,
+    /// Public access block of the bucket.
+    #[serde(default)]
+    pub public_access_block: Option<PublicAccessBlockProperties>,
+    /// Default encryption of the objects of the bucket.
+    #[serde(default)]
+    pub encryption: Option<EncryptionProperties>,
+    /// Tags of the bucket.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Either true to block all public access, or the individual settings.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PublicAccessBlockProperties {
+    All(bool),
+    Settings(PublicAccessBlockSettings),
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PublicAccessBlockSettings {
+    #[serde(default)]
+    pub block_public_acls: bool,
+    #[serde(default)]
+    pub ignore_public_acls: bool,
+    #[serde(default)]
+    pub block_public_policy: bool,
+    #[serde(default)]
+    pub restrict_public_buckets: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionProperties {
+    /// Either "AES256" or "aws:kms".
+    pub algorithm: String,
+    #[serde(default)]
+    pub kms_key_id: Option<String>,
+    #[serde(default)]
+    pub bucket_key: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionProperties {
+    /// Object Lock mode of the written objects.
+    pub mode: RetentionMode,
+    /// Time, in seconds, during which the written objects can't be deleted nor overwritten.
+    pub duration: NonZeroU64,
+    /// Whether a legal hold is placed on the written objects.
+    #[serde(default)]
+    pub legal_hold: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionMode {
+    Governance,
+    Compliance,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitProperties {
+    /// Maximum amount of GET requests per second.
+    #[serde(default)]
+    pub get: Option<f64>,
+    /// Maximum amount of PUT requests per second.
+    #[serde(default)]
+    pub put: Option<f64>,
+    /// Maximum amount of LIST requests per second.
+    #[serde(default)]
+    pub list: Option<f64>,
+    /// Maximum amount of DELETE requests per second.
+    #[serde(default)]
+    pub delete: Option<f64>,
+    /// Time, in seconds, during which the unused requests are accumulated.
+    #[serde(default)]
+    pub burst: Option<f64>,
+}
+
+/// A property either applying to all the keys of the storage, or associating key expressions
+/// (relative to `strip_prefix`) to values.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PerKeyExpr<T> {
+    All(T),
+    KeyExprs(BTreeMap<String, T>),
+}
+
+impl<T> PerKeyExpr<T> {
+    /// The values associated to their key expressions.
+    pub fn into_rules(self) -> Vec<(String, T)> {
+        match self {
+            PerKeyExpr::All(value) => vec![(ALL_KEYS.to_owned(), value)],
+            PerKeyExpr::KeyExprs(rules) => rules.into_iter().collect(),
+        }
+    }
+}
+
+fn default_on_closure_timeout() -> NonZeroU64 {
+    NonZeroU64::new(DEFAULT_ON_CLOSURE_TIMEOUT_SECS).unwrap()
+}
+
+fn default_ttl_sweep_interval() -> NonZeroU64 {
+    NonZeroU64::new(DEFAULT_TTL_SWEEP_INTERVAL_SECS).unwrap()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_concurrent_requests() -> NonZeroUsize {
+    NonZeroUsize::new(DEFAULT_MAX_CONCURRENT_REQUESTS).unwrap()
+}
+
+fn default_poll_interval() -> NonZeroU64 {
+    NonZeroU64::new(DEFAULT_POLL_INTERVAL_SECS).unwrap()
+}
+
+fn default_cache_max_size() -> usize {
+    DEFAULT_CACHE_MAX_SIZE
+}
+
+fn default_cache_ttl() -> u64 {
+    DEFAULT_CACHE_TTL_SECS
+}
+
+fn default_disk_cache_max_size() -> u64 {
+    DEFAULT_DISK_CACHE_MAX_SIZE
+}
+
+fn default_reconcile_interval() -> NonZeroU64 {
+    NonZeroU64::new(DEFAULT_RECONCILE_INTERVAL_SECS).unwrap()
+}
+
+impl StorageProperties {
+    /// Deserializes the `volume` field of a storage configuration, failing with an error naming
+    /// the offending property.
+    pub fn new(volume_cfg: &Value) -> ZResult<Self> {
+        serde_path_to_error::deserialize(volume_cfg).map_err(|e| {
+            let path = e.path().to_string();
+            if path == "." {
+                zerror!("Invalid S3 storage configuration: {}", e.inner()).into()
+            } else {
+                zerror!(
+                    "Invalid property `{}` of S3 storage configuration: {}",
+                    path,
+                    e.inner()
+                )
+                .into()
+            }
+        })
+    }
+}
+
+/// JSON schema of the `volume` field of the S3 storage configurations, against which the
+/// configurations can be validated before being deployed.
+pub fn json_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(StorageProperties)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(volume_cfg: Value) -> String {
+        match StorageProperties::new(&volume_cfg) {
+            Ok(_) => panic!("{volume_cfg} should be rejected"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn nested_sections_are_typed() {
+        let properties = StorageProperties::new(&serde_json::json!({
+            "bucket": "zenoh-bucket",
+            "cache": { "max_size": 1024 },
+            "change_detection": { "mode": "events", "endpoint": "127.0.0.1:9871" },
+            "retention": { "**": { "mode": "governance", "duration": 60 } },
+        }))
+        .unwrap();
+        let cache = properties.cache.unwrap();
+        assert_eq!(cache.max_size, 1024);
+        assert_eq!(cache.ttl, DEFAULT_CACHE_TTL_SECS);
+        let change_detection = properties.change_detection.unwrap();
+        assert!(matches!(change_detection.mode, ChangeDetectionMode::Events));
+        assert_eq!(change_detection.interval.get(), DEFAULT_POLL_INTERVAL_SECS);
+        assert_eq!(properties.retention["**"].duration.get(), 60);
+    }
+
+    #[test]
+    fn unknown_nested_properties_are_rejected() {
+        let e = error(serde_json::json!({
+            "bucket": "zenoh-bucket",
+            "disk_cache": { "path": "/tmp/cache", "max_sizes": 1024 },
+        }));
+        assert!(e.contains("`disk_cache.max_sizes`"), "{e}");
+        let e = error(serde_json::json!({
+            "bucket": "zenoh-bucket",
+            "lifecycle": { "rules": [{ "id": "tmp", "expiration_days": 0 }] },
+        }));
+        assert!(e.contains("`lifecycle.rules[0].expiration_days`"), "{e}");
+        let e = error(serde_json::json!({
+            "bucket": "zenoh-bucket",
+            "rate_limit": { "get": "fast" },
+        }));
+        assert!(e.contains("`rate_limit.get`"), "{e}");
+    }
+}
@@ -0,0 +1,310 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+use crate::properties::RateLimitProperties;
+
+// Rate limit properties
+pub const RATE_LIMIT_PROP: &str = "rate_limit";
+const RATE_LIMIT_BURST: &str = "burst";
+
+/// Type of the S3 requests issued by a storage, each one being rate limited independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Get,
+    Put,
+    List,
+    Delete,
+}
+
+impl Operation {
+    const ALL: [Operation; 4] = [
+        Operation::Get,
+        Operation::Put,
+        Operation::List,
+        Operation::Delete,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::List => "list",
+            Operation::Delete => "delete",
+        }
+    }
+
+    /// Type of the request sent by [S3Client::send](crate::client::S3Client) under the given
+    /// operation name: the requests reading objects or settings are rate limited as GET requests,
+    /// the ones writing them as PUT requests.
+    pub fn of(operation: &str) -> Self {
+        match operation {
+            "list" => Operation::List,
+            "delete" | "delete_bucket" => Operation::Delete,
+            "put" | "copy" | "create_bucket" | "configure_bucket" => Operation::Put,
+            _ => Operation::Get,
+        }
+    }
+}
+
+/// Configuration of the rate limits of the requests issued by a storage, declared under the
+/// `rate_limit` field of the storage configuration:
+///
+/// ```
+/// rate_limit: {
+///   // Maximum amount of requests per second, per type of request. Requests of the types not
+///   // specified are not rate limited.
+///   get: 5000,
+///   put: 3000,
+///   list: 100,
+///   delete: 3000,
+///   // Time, in seconds, during which the unused requests can be accumulated to absorb bursts
+///   // (1 by default).
+///   burst: 2,
+/// }
+/// ```
+#[derive(Clone)]
+pub(crate) struct RateLimitConfig {
+    /// Requests per second, indexed by [Operation].
+    rates: [Option<f64>; 4],
+    burst: Duration,
+}
+
+impl RateLimitConfig {
+    /// Creates a new instance of [RateLimitConfig] from the `rate_limit` field of the storage
+    /// configuration.
+    pub fn new(properties: RateLimitProperties) -> ZResult<Self> {
+        let mut rates = [None; 4];
+        for (operation, rate) in [
+            (Operation::Get, properties.get),
+            (Operation::Put, properties.put),
+            (Operation::List, properties.list),
+            (Operation::Delete, properties.delete),
+        ] {
+            rates[operation as usize] = match rate {
+                Some(rate) if !(rate > 0.0 && rate.is_finite()) => {
+                    return Err(zerror!(
+                        "Property `{RATE_LIMIT_PROP}.{}` must be a positive number of requests \
+                            per second",
+                        operation.name()
+                    )
+                    .into())
+                }
+                rate => rate,
+            };
+        }
+        let burst = match properties.burst {
+            None => Duration::from_secs(1),
+            Some(burst) => Duration::try_from_secs_f64(burst)
+                .ok()
+                .filter(|burst| !burst.is_zero())
+                .ok_or_else(|| {
+                    zerror!(
+                        "Property `{RATE_LIMIT_PROP}.{RATE_LIMIT_BURST}` must be a positive \
+                            number of seconds"
+                    )
+                })?,
+        };
+        Ok(RateLimitConfig { rates, burst })
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: Duration) -> Self {
+        let capacity = (rate * burst.as_secs_f64()).max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Takes a token, waiting for it to be available if needed.
+    async fn take(&self) {
+        while let Err(wait) = self.try_take(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Refills the bucket up to the given time, then takes a token if available. Otherwise returns
+    /// the time to wait for a token to be available.
+    fn try_take(&self, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * self.rate)
+            .min(self.capacity);
+        *last = (*last).max(now);
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / self.rate))
+        }
+    }
+}
+
+/// Accounts for a request waiting in a queue of the [RequestLimiter] until dropped, so that the
+/// requests cancelled while waiting are not accounted for anymore.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn enter(queue: &'a AtomicUsize) -> Self {
+        queue.fetch_add(1, Ordering::Relaxed);
+        Queued(queue)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Limits the S3 requests issued by a storage, both in amount of requests in flight and in rate
+/// per type of request. Each request sent by the [S3Client](crate::client::S3Client) of the
+/// storage, including the retries and the requests of its background tasks, takes a permit.
+pub(crate) struct RequestLimiter {
+    max_concurrent_requests: usize,
+    requests: Arc<Semaphore>,
+    buckets: [Option<TokenBucket>; 4],
+    /// Amount of requests waiting for their rate limit, indexed by [Operation].
+    rate_limited: [AtomicUsize; 4],
+    /// Amount of requests waiting for a request slot, indexed by [Operation].
+    concurrency_limited: [AtomicUsize; 4],
+}
+
+impl RequestLimiter {
+    pub fn new(max_concurrent_requests: usize, rate_limit: Option<&RateLimitConfig>) -> Self {
+        let buckets = Operation::ALL.map(|operation| {
+            rate_limit
+                .and_then(|rate_limit| rate_limit.rates[operation as usize])
+                .map(|rate| TokenBucket::new(rate, rate_limit.unwrap().burst))
+        });
+        RequestLimiter {
+            max_concurrent_requests,
+            requests: Arc::new(Semaphore::new(max_concurrent_requests)),
+            buckets,
+            rate_limited: Default::default(),
+            concurrency_limited: Default::default(),
+        }
+    }
+
+    /// Waits until a request of the given type can be issued. The returned permit must be held
+    /// until the request completes.
+    pub async fn acquire(&self, operation: Operation) -> OwnedSemaphorePermit {
+        if let Some(bucket) = &self.buckets[operation as usize] {
+            let _queued = Queued::enter(&self.rate_limited[operation as usize]);
+            bucket.take().await;
+        }
+        let _queued = Queued::enter(&self.concurrency_limited[operation as usize]);
+        self.requests
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the request semaphore is never closed")
+    }
+
+    /// State of the limits, reported in the admin status of the storage.
+    pub fn status(&self) -> Value {
+        let queued: Map<String, Value> = Operation::ALL
+            .iter()
+            .map(|operation| {
+                let index = *operation as usize;
+                (
+                    operation.name().to_owned(),
+                    json!({
+                        "rate_limit": self.rate_limited[index].load(Ordering::Relaxed),
+                        "concurrency": self.concurrency_limited[index].load(Ordering::Relaxed),
+                    }),
+                )
+            })
+            .collect();
+        let rates: Map<String, Value> = Operation::ALL
+            .iter()
+            .filter_map(|operation| {
+                self.buckets[*operation as usize]
+                    .as_ref()
+                    .map(|bucket| (operation.name().to_owned(), json!(bucket.rate)))
+            })
+            .collect();
+        json!({
+            "in_flight": self.max_concurrent_requests - self.requests.available_permits(),
+            "max_concurrent_requests": self.max_concurrent_requests,
+            "queued": queued,
+            "rate_limits": rates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_buckets_refill_at_their_rate_up_to_their_capacity() {
+        let bucket = TokenBucket::new(10.0, Duration::from_secs(1));
+        let start = bucket.state.lock().unwrap().1;
+        // The bucket starts full, holding a second worth of requests.
+        for _ in 0..10 {
+            assert!(bucket.try_take(start).is_ok());
+        }
+        let wait = bucket.try_take(start).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.1).abs() < 1e-9);
+
+        // A token is available after 1/rate second.
+        assert!(bucket.try_take(start + Duration::from_millis(50)).is_err());
+        assert!(bucket.try_take(start + Duration::from_millis(100)).is_ok());
+        assert!(bucket.try_take(start + Duration::from_millis(100)).is_err());
+
+        // Idle time doesn't accumulate more tokens than the capacity.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(bucket.try_take(later).is_ok());
+        }
+        assert!(bucket.try_take(later).is_err());
+    }
+
+    #[test]
+    fn token_buckets_hold_at_least_one_token() {
+        let bucket = TokenBucket::new(0.5, Duration::from_secs(1));
+        let start = bucket.state.lock().unwrap().1;
+        assert!(bucket.try_take(start).is_ok());
+        let wait = bucket.try_take(start).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn client_operations_are_mapped_to_request_types() {
+        assert_eq!(Operation::of("head"), Operation::Get);
+        assert_eq!(Operation::of("get_bucket_configuration"), Operation::Get);
+        assert_eq!(Operation::of("copy"), Operation::Put);
+        assert_eq!(Operation::of("configure_bucket"), Operation::Put);
+        assert_eq!(Operation::of("list"), Operation::List);
+        assert_eq!(Operation::of("delete_bucket"), Operation::Delete);
+    }
+}
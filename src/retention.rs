@@ -0,0 +1,209 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_sdk_s3::model::{ObjectLockLegalHoldStatus, ObjectLockMode};
+use aws_sdk_s3::types::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use zenoh::Result as ZResult;
+use zenoh_core::zerror;
+
+use crate::client::S3Client;
+use crate::error::{with_context, S3Error, S3ErrorKind};
+use crate::properties::{RetentionMode, RetentionProperties};
+use crate::utils::KeyExprRules;
+
+// Retention properties
+pub const RETENTION_PROP: &str = "retention";
+
+/// S3 Object Lock settings applied to the objects written for the keys matching a key expression
+/// of the `retention` field of the storage configuration:
+///
+/// ```
+/// retention: {
+///   "regulated/**": {
+///     // Either "governance" or "compliance".
+///     mode: "compliance",
+///     // Time, in seconds, during which the written objects can't be deleted nor overwritten.
+///     duration: 31536000,
+///     // Whether a legal hold is placed on the written objects (false by default).
+///     legal_hold: true,
+///   },
+/// }
+/// ```
+///
+/// The bucket must have Object Lock enabled (see `bucket_creation.object_lock`).
+#[derive(Clone)]
+pub(crate) struct RetentionPolicy {
+    pub mode: ObjectLockMode,
+    pub duration: Duration,
+    pub legal_hold: bool,
+}
+
+impl RetentionPolicy {
+    /// Creates a new instance of [RetentionPolicy] from a value of the `retention` field of the
+    /// storage configuration.
+    pub fn new(properties: RetentionProperties) -> Self {
+        RetentionPolicy {
+            mode: match properties.mode {
+                RetentionMode::Governance => ObjectLockMode::Governance,
+                RetentionMode::Compliance => ObjectLockMode::Compliance,
+            },
+            duration: Duration::from_secs(properties.duration.get()),
+            legal_hold: properties.legal_hold,
+        }
+    }
+
+    /// Object Lock settings of an object written now under this policy.
+    pub fn object_lock(&self) -> ObjectLock {
+        let retain_until = SystemTime::now() + self.duration;
+        ObjectLock {
+            mode: self.mode.as_str().to_owned(),
+            retain_until: retain_until
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            legal_hold: self.legal_hold,
+        }
+    }
+}
+
+/// Object Lock settings of a written object.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ObjectLock {
+    pub mode: String,
+    /// Seconds since the UNIX epoch until which the object is retained.
+    pub retain_until: u64,
+    pub legal_hold: bool,
+}
+
+impl ObjectLock {
+    pub fn mode(&self) -> ObjectLockMode {
+        ObjectLockMode::from(self.mode.as_str())
+    }
+
+    pub fn retain_until_date(&self) -> DateTime {
+        DateTime::from_secs(self.retain_until as i64)
+    }
+
+    pub fn legal_hold_status(&self) -> Option<ObjectLockLegalHoldStatus> {
+        self.legal_hold.then_some(ObjectLockLegalHoldStatus::On)
+    }
+}
+
+/// Retention policies in effect, reported in the admin status of the storage.
+pub(crate) fn retention_status(rules: &KeyExprRules<RetentionPolicy>) -> Value {
+    let policies: Map<String, Value> = rules
+        .iter()
+        .map(|(key_expr, policy)| {
+            (
+                key_expr.to_string(),
+                json!({
+                    "mode": policy.mode.as_str(),
+                    "duration": policy.duration.as_secs(),
+                    "legal_hold": policy.legal_hold,
+                }),
+            )
+        })
+        .collect();
+    Value::Object(policies)
+}
+
+/// Fails with an explicit error if the object associated to the key is retained or under legal
+/// hold, as S3 would either reject its deletion or hide the object behind a delete marker.
+pub(crate) async fn check_not_locked(client: &S3Client, key: &str) -> ZResult<()> {
+    let head = match client.get_head_object(key).await {
+        Ok(head) => head,
+        Err(e) if S3Error::kind_of(&e) == Some(S3ErrorKind::NotFound) => return Ok(()),
+        Err(e) => {
+            return Err(with_context(
+                e,
+                format!("Unable to check the retention of '{key}'"),
+            ))
+        }
+    };
+    if head.object_lock_legal_hold_status() == Some(&ObjectLockLegalHoldStatus::On) {
+        return Err(zerror!("Object '{}' is under legal hold and can't be deleted", key).into());
+    }
+    if let Some(retain_until) = head.object_lock_retain_until_date() {
+        let retain_until = UNIX_EPOCH + Duration::from_secs(retain_until.secs().max(0) as u64);
+        if retain_until > SystemTime::now() {
+            return Err(zerror!(
+                "Object '{}' is retained in {} mode until {} and can't be deleted",
+                key,
+                head.object_lock_mode()
+                    .map_or("unknown", |mode| mode.as_str())
+                    .to_lowercase(),
+                humantime::format_rfc3339_seconds(retain_until)
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the error of a failed deletion by the explicit error of [check_not_locked] if the
+/// object turns out to be locked, e.g. by the default retention of the bucket rather than by the
+/// `retention` of the storage.
+pub(crate) async fn explain_delete_failure(
+    client: &S3Client,
+    key: &str,
+    err: zenoh_core::Error,
+) -> zenoh_core::Error {
+    match S3Error::kind_of(&err) {
+        Some(S3ErrorKind::AccessDenied | S3ErrorKind::Other) => {
+            check_not_locked(client, key).await.err().unwrap_or(err)
+        }
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(properties: Value) -> RetentionPolicy {
+        RetentionPolicy::new(serde_json::from_value(properties).unwrap())
+    }
+
+    #[test]
+    fn objects_are_retained_for_the_duration_from_now() {
+        let policy = policy(json!({ "mode": "compliance", "duration": 86400, "legal_hold": true }));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let object_lock = policy.object_lock();
+        assert_eq!(object_lock.mode(), ObjectLockMode::Compliance);
+        assert!(object_lock.retain_until >= now + 86400);
+        assert!(object_lock.retain_until <= now + 86401);
+        assert_eq!(
+            object_lock.retain_until_date(),
+            DateTime::from_secs(object_lock.retain_until as i64)
+        );
+        assert_eq!(
+            object_lock.legal_hold_status(),
+            Some(ObjectLockLegalHoldStatus::On)
+        );
+    }
+
+    #[test]
+    fn legal_holds_are_only_placed_on_request() {
+        let object_lock = policy(json!({ "mode": "governance", "duration": 60 })).object_lock();
+        assert_eq!(object_lock.mode(), ObjectLockMode::Governance);
+        assert_eq!(object_lock.legal_hold_status(), None);
+    }
+}
@@ -0,0 +1,74 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+use zenoh::config::Config;
+use zenoh::prelude::r#async::*;
+use zenoh::Result as ZResult;
+use zenoh::Session;
+use zenoh_core::zerror;
+
+/// Endpoint the sessions of the storages connect to if none is configured: the default endpoint
+/// of a zenoh router running on the same host.
+pub const DEFAULT_SESSION_ENDPOINT: &str = "tcp/127.0.0.1:7447";
+
+/// Zenoh session of a storage, shared by the features publishing or serving queries on its behalf
+/// (`change_detection` and `fsck.admin_queryable`). It's opened on first use, so that the storage
+/// creation doesn't depend on the router it connects to.
+pub(crate) struct StorageSession {
+    connect: Vec<String>,
+    session: OnceCell<Arc<Session>>,
+}
+
+impl StorageSession {
+    pub fn new(connect: Vec<String>) -> Self {
+        StorageSession {
+            connect,
+            session: OnceCell::new(),
+        }
+    }
+
+    /// Returns the session of the storage, opening it if needed.
+    pub async fn get(&self) -> ZResult<Arc<Session>> {
+        self.session
+            .get_or_try_init(|| async { open_session(&self.connect).await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+}
+
+/// Opens a zenoh session on behalf of a storage, the backend having no access to the session of
+/// the router it's loaded by. The session runs in client mode with multicast scouting disabled,
+/// so that it only connects to the endpoints specified (or [DEFAULT_SESSION_ENDPOINT]) instead of
+/// joining whatever zenoh system is reachable on the network.
+pub(crate) async fn open_session(connect: &[String]) -> ZResult<Session> {
+    let endpoints = if connect.is_empty() {
+        vec![DEFAULT_SESSION_ENDPOINT.to_owned()]
+    } else {
+        connect.to_vec()
+    };
+    let mut config = Config::default();
+    config
+        .insert_json5("mode", r#""client""#)
+        .map_err(|e| zerror!("Invalid session mode: {e:?}"))?;
+    config
+        .insert_json5("scouting/multicast/enabled", "false")
+        .map_err(|e| zerror!("Unable to disable scouting: {e:?}"))?;
+    config
+        .insert_json5("connect/endpoints", &serde_json::to_string(&endpoints)?)
+        .map_err(|e| zerror!("Invalid endpoints {endpoints:?}: {e:?}"))?;
+    zenoh::open(config).res().await
+}
@@ -0,0 +1,297 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use zenoh::prelude::{Encoding, OwnedKeyExpr};
+use zenoh::time::Timestamp;
+use zenoh::value::Value;
+use zenoh::Result as ZResult;
+use zenoh_buffers::buffer::SplitBuffer;
+use zenoh_core::zerror;
+use zenoh_util::time_range::{TimeBound, TimeRange};
+
+use crate::client::{BucketLocation, PutObjectOptions, S3Client};
+use crate::config::ForeignObjectsConfig;
+use crate::error::{with_context, S3Error, S3ErrorKind};
+use crate::utils::{is_expired, S3Key};
+use crate::{
+    get_stored_value, object_timestamp, stored_versions, NONE_KEY, TIMESTAMP_METADATA_KEY,
+};
+
+/// Sample of a snapshot archive. Archives are newline-delimited JSON streams of records.
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    /// Key of the sample, relative to the `strip_prefix` of the storage. None stands for the
+    /// `strip_prefix` itself.
+    pub key: Option<String>,
+    /// Timestamp of the sample.
+    pub timestamp: String,
+    /// Encoding of the sample.
+    pub encoding: String,
+    /// Payload of the sample, encoded in base64.
+    pub payload: String,
+}
+
+/// Entry of the manifest written into the bucket by the `snapshot` closure strategy. Manifests
+/// are newline-delimited JSON streams of records.
+#[derive(Serialize, Deserialize)]
+pub struct ManifestRecord {
+    /// Key of the sample, relative to the `strip_prefix` of the storage. None stands for the
+    /// `strip_prefix` itself.
+    pub key: Option<String>,
+    /// Timestamp of the sample.
+    pub timestamp: String,
+}
+
+/// Exports the samples held by the storage associated to the bucket at the time specified (now
+/// if None) to the writer, as a newline-delimited JSON stream of [SnapshotRecord]s. Returns the
+/// amount of exported samples.
+///
+/// Exporting the samples at a past time requires `versioning`, the versions of the objects alive
+/// at that time being exported: the current objects alone would miss the keys overwritten since.
+pub async fn export_snapshot<W: Write>(
+    location: &BucketLocation,
+    at: Option<SystemTime>,
+    versioning: bool,
+    mut writer: W,
+) -> ZResult<usize> {
+    let client = location.client().await?;
+    write_snapshot(&client, at, versioning, &mut writer).await
+}
+
+pub(crate) async fn write_snapshot<W: Write>(
+    client: &S3Client,
+    at: Option<SystemTime>,
+    versioning: bool,
+    writer: &mut W,
+) -> ZResult<usize> {
+    let mut count = 0;
+    match (versioning, at) {
+        (false, Some(_)) => {
+            return Err(zerror!(
+                "Exporting the samples at a past time requires the object versions of the bucket, \
+                    hence `versioning`"
+            )
+            .into())
+        }
+        (true, Some(at)) => {
+            // The keys deleted since that time have no current object, hence all the object
+            // versions of the bucket are listed.
+            for (object_key, (versions, delete_markers)) in client.list_bucket_versions().await? {
+                let stored_value = stored_versions(
+                    client,
+                    &object_key,
+                    &versions,
+                    &delete_markers,
+                    TimeRange(TimeBound::Inclusive(at), TimeBound::Inclusive(at)),
+                    None,
+                )
+                .await
+                .map(|mut versions| {
+                    versions
+                        .pop()
+                        .map(|stored_data| (stored_data.timestamp, stored_data.value))
+                });
+                count += write_record(client, &object_key, stored_value, writer)?;
+            }
+        }
+        _ => {
+            for object in client.list_objects_in_bucket().await? {
+                let object_key = match object.key() {
+                    Some(key) => key,
+                    None => continue,
+                };
+                let stored_value = get_stored_value(client, object_key, None).await;
+                count += write_record(client, object_key, stored_value, writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+    log::debug!("Exported {} samples from '{}'.", count, client);
+    Ok(count)
+}
+
+/// Writes the [SnapshotRecord] of the sample retrieved from an object, if any. Returns the amount
+/// of written records.
+fn write_record<W: Write>(
+    client: &S3Client,
+    object_key: &str,
+    stored_value: ZResult<Option<(Timestamp, Value)>>,
+    writer: &mut W,
+) -> ZResult<usize> {
+    let (timestamp, value) = match stored_value {
+        Ok(Some(stored_value)) => stored_value,
+        Ok(None) => return Ok(0),
+        Err(e) => {
+            log::warn!("Skipping object '{}' of '{}': {}", object_key, client, e);
+            return Ok(0);
+        }
+    };
+
+    let key = object_key.trim_start_matches('/');
+    let record = SnapshotRecord {
+        key: (key != NONE_KEY).then(|| key.to_owned()),
+        timestamp: timestamp.to_string(),
+        encoding: value.encoding.to_string(),
+        payload: general_purpose::STANDARD.encode(value.payload.contiguous()),
+    };
+    serde_json::to_writer(&mut *writer, &record)?;
+    writer.write_all(b"\n")?;
+    Ok(1)
+}
+
+/// Writes the keys and timestamps of the samples currently held by the bucket to the writer, as a
+/// newline-delimited JSON stream of [ManifestRecord]s. Returns the amount of listed samples.
+pub(crate) async fn write_manifest<W: Write>(
+    client: &S3Client,
+    foreign: Option<&ForeignObjectsConfig>,
+    writer: &mut W,
+) -> ZResult<usize> {
+    let mut count = 0;
+    for object in client.list_objects_in_bucket().await? {
+        let object_key = match object.key() {
+            Some(key) => key,
+            None => continue,
+        };
+        let timestamp = match client.get_head_object(object_key).await {
+            Ok(head) if head.metadata().map_or(false, is_expired) => continue,
+            Ok(head) => {
+                object_timestamp(object_key, head.metadata(), head.last_modified(), foreign)
+            }
+            Err(e) => Err(e),
+        };
+        let timestamp = match timestamp {
+            Ok(timestamp) => timestamp,
+            Err(e) => {
+                log::warn!("Skipping object '{}' of '{}': {}", object_key, client, e);
+                continue;
+            }
+        };
+
+        let key = object_key.trim_start_matches('/');
+        let record = ManifestRecord {
+            key: (key != NONE_KEY).then(|| key.to_owned()),
+            timestamp: timestamp.to_string(),
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Outcome of the import of a snapshot archive.
+#[derive(Default)]
+pub struct ImportReport {
+    /// Amount of samples written into the bucket.
+    pub imported: usize,
+    /// Amount of samples skipped because the bucket holds a more recent sample for their key.
+    pub skipped: usize,
+}
+
+/// Imports the samples of a snapshot archive, as written by [export_snapshot], into the bucket
+/// of a storage whose `strip_prefix` is `path_prefix`. The original timestamps of the samples are
+/// preserved and the last writer wins: samples older than the ones already stored for the same
+/// key are skipped.
+pub async fn import_snapshot<R: BufRead>(
+    location: &BucketLocation,
+    path_prefix: Option<String>,
+    reader: R,
+) -> ZResult<ImportReport> {
+    let client = location.client().await?;
+    let mut report = ImportReport::default();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: SnapshotRecord = serde_json::from_str(&line)
+            .map_err(|e| zerror!("Invalid record at line {}: {}", line_number + 1, e))?;
+        if import_record(&client, path_prefix.to_owned(), record).await? {
+            report.imported += 1;
+        } else {
+            report.skipped += 1;
+        }
+    }
+    log::debug!(
+        "Imported {} samples into '{}', skipped {}.",
+        report.imported,
+        client,
+        report.skipped
+    );
+    Ok(report)
+}
+
+/// Writes the record into the bucket unless a more recent sample is stored for its key. Returns
+/// true if the record was written.
+async fn import_record(
+    client: &S3Client,
+    path_prefix: Option<String>,
+    record: SnapshotRecord,
+) -> ZResult<bool> {
+    let key = record.key.as_deref().unwrap_or(NONE_KEY);
+    let key_expr = OwnedKeyExpr::from_str(key)?;
+    let timestamp = Timestamp::from_str(&record.timestamp)
+        .map_err(|e| zerror!("Invalid timestamp for key '{}': {:?}", key, e))?;
+    let object_key: String = S3Key::from_key_expr(path_prefix, key_expr)?.into();
+
+    match client.get_head_object(&object_key).await {
+        Ok(head) => {
+            let stored_timestamp = head
+                .metadata()
+                .and_then(|metadata| metadata.get(TIMESTAMP_METADATA_KEY))
+                .and_then(|timestamp| Timestamp::from_str(timestamp).ok());
+            if stored_timestamp.map_or(false, |stored_timestamp| stored_timestamp >= timestamp) {
+                log::debug!("Skipping '{}', a more recent sample is stored.", key);
+                return Ok(false);
+            }
+        }
+        Err(e) if S3Error::kind_of(&e) == Some(S3ErrorKind::NotFound) => {}
+        Err(e) => {
+            return Err(with_context(
+                e,
+                format!("Unable to check existing object '{key}'"),
+            ))
+        }
+    }
+
+    let payload = general_purpose::STANDARD
+        .decode(&record.payload)
+        .map_err(|e| zerror!("Invalid payload for key '{}': {}", key, e))?;
+    let value = Value::from(payload);
+    let value = match Encoding::try_from(record.encoding) {
+        Ok(encoding) => value.encoding(encoding),
+        Err(_) => value,
+    };
+    let metadata = [(TIMESTAMP_METADATA_KEY.to_string(), timestamp.to_string())]
+        .into_iter()
+        .collect();
+    client
+        .put_object(
+            object_key,
+            value,
+            Some(metadata),
+            PutObjectOptions::default(),
+        )
+        .await?;
+    Ok(true)
+}
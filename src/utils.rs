@@ -14,10 +14,18 @@
 use core::fmt;
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aws_sdk_s3::types::DateTime;
+use uhlc::{ID, NTP64};
 use zenoh::prelude::KeyExpr;
+use zenoh::time::Timestamp;
 use zenoh::value::Value;
 use zenoh::Result as ZResult;
-use zenoh_keyexpr::OwnedKeyExpr;
+use zenoh_core::zerror;
+use zenoh_keyexpr::{keyexpr, OwnedKeyExpr};
+
+use crate::{EXPIRES_AT_METADATA_KEY, TOMBSTONE_METADATA_KEY};
 
 pub struct S3Value {
     pub key: S3Key,
@@ -78,3 +86,415 @@ impl std::fmt::Display for S3Key {
         }
     }
 }
+
+/// Values associated to key expressions, used to configure per key expression behaviours of a
+/// storage (for instance a different TTL for `archive/**`). The key expressions are relative to
+/// the `strip_prefix` of the storage, that is they are matched against the stripped keys.
+///
+/// When several key expressions match a key, the most specific one (the longest) is applied.
+#[derive(Clone)]
+pub struct KeyExprRules<T> {
+    rules: Vec<(OwnedKeyExpr, T)>,
+}
+
+impl<T> KeyExprRules<T> {
+    pub fn new(mut rules: Vec<(OwnedKeyExpr, T)>) -> Self {
+        rules.sort_by(|(a, _), (b, _)| {
+            b.as_str()
+                .len()
+                .cmp(&a.as_str().len())
+                .then_with(|| a.as_str().cmp(b.as_str()))
+        });
+        Self { rules }
+    }
+
+    /// Returns the rule (key expression and value) applying to the key specified if any.
+    pub fn lookup_rule(&self, key: &keyexpr) -> Option<&(OwnedKeyExpr, T)> {
+        self.rules
+            .iter()
+            .find(|(key_expr, _)| key_expr.includes(key))
+    }
+
+    /// Returns the value applying to the key specified if any.
+    pub fn lookup(&self, key: &keyexpr) -> Option<&T> {
+        self.lookup_rule(key).map(|(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(OwnedKeyExpr, T)> {
+        self.rules.iter()
+    }
+}
+
+impl<T> Default for KeyExprRules<T> {
+    fn default() -> Self {
+        Self { rules: vec![] }
+    }
+}
+
+/// Builds a timestamp out of an S3 date (e.g. the `LastModified` date of an object) and the zenoh
+/// ID specified.
+pub fn timestamp_from_date_time(date_time: &DateTime, id: ID) -> Timestamp {
+    let time = Duration::new(date_time.secs().max(0) as u64, date_time.subsec_nanos());
+    Timestamp::new(NTP64::from(time), id)
+}
+
+/// Converts an S3 date (e.g. the `LastModified` date of an object) to a system time.
+pub fn system_time_from_date_time(date_time: &DateTime) -> SystemTime {
+    UNIX_EPOCH + Duration::new(date_time.secs().max(0) as u64, date_time.subsec_nanos())
+}
+
+/// Computes the expiry date, as seconds since the UNIX epoch, of a sample with the timestamp
+/// specified and stored with the given time to live.
+pub fn expiry_from(timestamp: &Timestamp, ttl: &Duration) -> u64 {
+    (timestamp.get_time().to_system_time() + *ttl)
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |expiry| expiry.as_secs())
+}
+
+/// Returns true if the object metadata contains an expiry date which is already past.
+pub fn is_expired(metadata: &HashMap<String, String>) -> bool {
+    metadata
+        .get(EXPIRES_AT_METADATA_KEY)
+        .and_then(|expiry| expiry.parse::<u64>().ok())
+        .map_or(false, |expiry| {
+            UNIX_EPOCH + Duration::from_secs(expiry) <= SystemTime::now()
+        })
+}
+
+/// Returns true if the object metadata marks a tombstone, i.e. a version recording the deletion
+/// of the sample in a versioned bucket.
+pub fn is_tombstone(metadata: &HashMap<String, String>) -> bool {
+    metadata
+        .get(TOMBSTONE_METADATA_KEY)
+        .map_or(false, |tombstone| tombstone == "true")
+}
+
+// Maximum amount of tags S3 allows on a single object.
+const MAX_OBJECT_TAGS: usize = 10;
+// Maximum length, in characters, S3 allows for tag names.
+const MAX_TAG_NAME_LENGTH: usize = 128;
+// Maximum length, in characters, S3 allows for tag values.
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+/// Templates of the S3 tags attached to the objects written on the keys matching a key
+/// expression. The tag values may refer to the following variables:
+/// * `${N}`: the key chunk matched by the N-th wildcard chunk of the key expression (e.g. `${1}`
+///     is `truck-1` for the key `fleet/truck-1/telemetry` matching `fleet/*/telemetry`)
+/// * `${key}`: the key of the sample
+/// * `${encoding}`: the encoding of the sample
+///
+/// Tag names and the literal parts of the templates must comply with the S3 limits: letters,
+/// digits, spaces and `+ - = . _ : / @` only, up to 128 characters for names and 256 for values.
+/// The characters of the rendered values which S3 doesn't allow are replaced by `_` and the
+/// values are truncated to 256 characters.
+#[derive(Clone)]
+pub struct TagTemplates {
+    templates: Vec<(String, String)>,
+}
+
+impl TagTemplates {
+    pub fn new(templates: Vec<(String, String)>) -> ZResult<Self> {
+        if templates.len() > MAX_OBJECT_TAGS {
+            return Err(zerror!("S3 objects can't have more than {MAX_OBJECT_TAGS} tags").into());
+        }
+        for (name, template) in templates.iter() {
+            let length = name.chars().count();
+            if length == 0 || length > MAX_TAG_NAME_LENGTH || !name.chars().all(is_tag_char) {
+                return Err(zerror!(
+                    "Invalid tag name '{name}': must be 1 to {MAX_TAG_NAME_LENGTH} letters, \
+                        digits, spaces or `+ - = . _ : / @`"
+                )
+                .into());
+            }
+            for variable in template_variables(template) {
+                if variable != "key" && variable != "encoding" && variable.parse::<usize>().is_err()
+                {
+                    return Err(zerror!(
+                        "Unknown variable '${{{variable}}}' in the template of tag '{name}'"
+                    )
+                    .into());
+                }
+            }
+            let literal = render_template(template, &[], "", "");
+            if literal.chars().count() > MAX_TAG_VALUE_LENGTH || !literal.chars().all(is_tag_char) {
+                return Err(zerror!(
+                    "Invalid template '{template}' of tag '{name}': values must be up to \
+                        {MAX_TAG_VALUE_LENGTH} letters, digits, spaces or `+ - = . _ : / @`"
+                )
+                .into());
+            }
+        }
+        Ok(Self { templates })
+    }
+
+    /// Renders the tags for a key matching the key expression these templates are associated
+    /// to, encoded as expected by the `x-amz-tagging` header.
+    pub fn render(&self, key_expr: &keyexpr, key: &keyexpr, encoding: &str) -> String {
+        let captures = wildcard_captures(key_expr, key);
+        self.templates
+            .iter()
+            .map(|(name, template)| {
+                let value = render_template(template, &captures, key.as_str(), encoding)
+                    .chars()
+                    .map(|c| if is_tag_char(c) { c } else { '_' })
+                    .take(MAX_TAG_VALUE_LENGTH)
+                    .collect::<String>();
+                format!("{}={}", percent_encode(name), percent_encode(&value))
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// Returns true if S3 allows the character in tag names and values.
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || " +-=._:/@".contains(c)
+}
+
+/// Returns the chunks of the key matched by the wildcard chunks of the key expression, up to
+/// the first `**` chunk after which chunks can't be paired anymore.
+fn wildcard_captures<'a>(key_expr: &keyexpr, key: &'a keyexpr) -> Vec<&'a str> {
+    key_expr
+        .as_str()
+        .split('/')
+        .zip(key.as_str().split('/'))
+        .take_while(|(pattern, _)| *pattern != "**")
+        .filter(|(pattern, _)| pattern.contains('*'))
+        .map(|(_, chunk)| chunk)
+        .collect()
+}
+
+fn template_variables(template: &str) -> Vec<&str> {
+    let mut variables = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        match rest[start + 2..].find('}') {
+            Some(end) => {
+                variables.push(&rest[start + 2..start + 2 + end]);
+                rest = &rest[start + 3 + end..];
+            }
+            None => break,
+        }
+    }
+    variables
+}
+
+fn render_template(template: &str, captures: &[&str], key: &str, encoding: &str) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start + 2..].find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        match &rest[start + 2..start + 2 + end] {
+            "key" => rendered.push_str(key),
+            "encoding" => rendered.push_str(encoding),
+            index => {
+                if let Some(capture) = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| index.checked_sub(1))
+                    .and_then(|index| captures.get(index))
+                {
+                    rendered.push_str(capture);
+                }
+            }
+        }
+        rest = &rest[start + 3 + end..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Percent-encodes all the characters of the string but the unreserved ones (RFC 3986).
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Decodes a percent-encoded string in which spaces may be encoded as '+', as the object keys of
+/// S3 event notifications. Returns None if the string isn't valid.
+pub fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+    while let Some(byte) = chars.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let high = (chars.next()? as char).to_digit(16)?;
+                let low = (chars.next()? as char).to_digit(16)?;
+                bytes.push((high * 16 + low) as u8);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Records the time elapsed since its creation in the `latency_ms` field of the current span when
+/// dropped, i.e. when the traced storage operation or S3 request completes.
+pub(crate) struct LatencyRecorder {
+    span: tracing::Span,
+    start: Instant,
+}
+
+impl LatencyRecorder {
+    pub fn start() -> Self {
+        LatencyRecorder {
+            span: tracing::Span::current(),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for LatencyRecorder {
+    fn drop(&mut self) {
+        self.span
+            .record("latency_ms", self.start.elapsed().as_millis() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules<T>(rules: Vec<(&str, T)>) -> KeyExprRules<T> {
+        KeyExprRules::new(
+            rules
+                .into_iter()
+                .map(|(key_expr, value)| (OwnedKeyExpr::try_from(key_expr).unwrap(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn key_expr_rules_apply_the_longest_match() {
+        let rules = rules(vec![("**", 1), ("a/b/*", 3), ("a/**", 2)]);
+        let lookup = |key: &str| rules.lookup(keyexpr::new(key).unwrap()).copied();
+        assert_eq!(lookup("a/b/c"), Some(3));
+        assert_eq!(lookup("a/b/c/d"), Some(2));
+        assert_eq!(lookup("a/x"), Some(2));
+        assert_eq!(lookup("z"), Some(1));
+        assert_eq!(
+            rules
+                .lookup_rule(keyexpr::new("a/b/c").unwrap())
+                .map(|(key_expr, _)| key_expr.as_str()),
+            Some("a/b/*")
+        );
+    }
+
+    #[test]
+    fn key_expr_rules_without_match() {
+        let rules = rules(vec![("a/**", ())]);
+        assert!(rules.lookup(keyexpr::new("b/c").unwrap()).is_none());
+        assert!(KeyExprRules::<()>::default()
+            .lookup(keyexpr::new("a").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn expiry_is_the_timestamp_plus_the_ttl() {
+        let timestamp = uhlc::HLC::default().new_timestamp();
+        let ttl = Duration::from_secs(3600);
+        let expected = (timestamp.get_time().to_system_time() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(expiry_from(&timestamp, &ttl), expected);
+    }
+
+    #[test]
+    fn expiry_is_read_from_the_metadata() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let metadata = |expiry: &str| {
+            HashMap::from([(EXPIRES_AT_METADATA_KEY.to_string(), expiry.to_string())])
+        };
+        assert!(is_expired(&metadata(&(now - 1).to_string())));
+        assert!(!is_expired(&metadata(&(now + 3600).to_string())));
+        assert!(!is_expired(&metadata("tomorrow")));
+        assert!(!is_expired(&HashMap::new()));
+    }
+
+    fn tag_templates(templates: Vec<(&str, &str)>) -> ZResult<TagTemplates> {
+        TagTemplates::new(
+            templates
+                .into_iter()
+                .map(|(name, template)| (name.to_owned(), template.to_owned()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn tags_are_rendered_from_the_key() {
+        let tags =
+            tag_templates(vec![("vehicle", "${1}"), ("source", "zenoh:${encoding}")]).unwrap();
+        let key_expr = keyexpr::new("fleet/*/telemetry").unwrap();
+        let key = keyexpr::new("fleet/truck-1/telemetry").unwrap();
+        assert_eq!(
+            tags.render(key_expr, key, "text/plain"),
+            "vehicle=truck-1&source=zenoh%3Atext%2Fplain"
+        );
+    }
+
+    #[test]
+    fn rendered_tags_comply_with_the_s3_limits() {
+        let tags = tag_templates(vec![("key", "${key}")]).unwrap();
+        let key_expr = keyexpr::new("**").unwrap();
+        assert_eq!(
+            tags.render(key_expr, keyexpr::new("a,b").unwrap(), ""),
+            "key=a_b"
+        );
+        let long_key = ["chunk"; 100].join("/");
+        let rendered = tags.render(key_expr, keyexpr::new(&long_key).unwrap(), "");
+        assert_eq!(
+            percent_decode(rendered.strip_prefix("key=").unwrap())
+                .unwrap()
+                .len(),
+            MAX_TAG_VALUE_LENGTH
+        );
+    }
+
+    #[test]
+    fn invalid_tag_templates_are_rejected() {
+        assert!(tag_templates(vec![("", "value")]).is_err());
+        assert!(tag_templates(vec![("a&b", "value")]).is_err());
+        assert!(tag_templates(vec![("name", "a,b")]).is_err());
+        assert!(tag_templates(vec![("name", "${unknown}")]).is_err());
+        let long_value = "v".repeat(MAX_TAG_VALUE_LENGTH + 1);
+        assert!(tag_templates(vec![("name", &long_value)]).is_err());
+        let names = (0..=MAX_OBJECT_TAGS)
+            .map(|i| format!("tag{i}"))
+            .collect::<Vec<_>>();
+        assert!(tag_templates(names.iter().map(|name| (name.as_str(), "v")).collect()).is_err());
+    }
+
+    #[test]
+    fn percent_encode_keeps_unreserved_characters_only() {
+        assert_eq!(percent_encode("aZ09-_.~"), "aZ09-_.~");
+        assert_eq!(percent_encode("a b/c=d&e"), "a%20b%2Fc%3Dd%26e");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+        assert_eq!(percent_decode(&percent_encode("a b/é")).unwrap(), "a b/é");
+        assert_eq!(percent_decode("a+b%2Fc").unwrap(), "a b/c");
+        assert!(percent_decode("%G1").is_none());
+    }
+}